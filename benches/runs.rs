@@ -0,0 +1,75 @@
+//! Compares finding runs pixel by pixel in `encode` with scanning for them in `encode_rgba`,
+//! which is vectorized when the `simd` feature is enabled. To compare the scalar and SIMD
+//! scans directly, save a baseline without the feature and then compare against it:
+//!
+//! ```sh
+//! cargo bench --bench runs -- --save-baseline scalar
+//! cargo bench --bench runs --features simd -- --baseline scalar
+//! ```
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use qoi::Pixel;
+use std::num::NonZeroUsize;
+
+const WIDTH: usize = 512;
+const HEIGHT: usize = 512;
+
+/// Images that cover the range from all runs to none, as interleaved RGBA bytes.
+fn images() -> Vec<(&'static str, Vec<u8>)> {
+    let image = |f: &dyn Fn(usize, usize) -> [u8; 4]| -> Vec<u8> {
+        (0..HEIGHT)
+            .flat_map(|y| (0..WIDTH).map(move |x| (x, y)))
+            .flat_map(|(x, y)| f(x, y))
+            .collect()
+    };
+    let mut seed = 0x2545_f491_u32;
+    let mut noise = move || {
+        seed ^= seed << 13;
+        seed ^= seed >> 17;
+        seed ^= seed << 5;
+        seed.to_le_bytes()
+    };
+    vec![
+        ("solid", image(&|_, _| [40, 80, 120, 255])),
+        (
+            "blocks",
+            image(&|x, y| [(x / 37 * 50) as u8, (y / 23 * 30) as u8, 90, 255]),
+        ),
+        ("gradient", image(&|x, y| [x as u8, y as u8, 0, 255])),
+        ("noise", (0..WIDTH * HEIGHT).flat_map(|_| noise()).collect()),
+    ]
+}
+
+fn encode_runs(c: &mut Criterion) {
+    let width = NonZeroUsize::new(WIDTH).unwrap();
+    let height = NonZeroUsize::new(HEIGHT).unwrap();
+    let mut output = Vec::new();
+
+    let mut group = c.benchmark_group("encode");
+    group.throughput(Throughput::Bytes((WIDTH * HEIGHT * 4) as u64));
+    for (name, rgba) in images() {
+        let pixels: Vec<Pixel> = rgba
+            .chunks_exact(4)
+            .map(|c| Pixel::rgba(c[0], c[1], c[2], c[3]))
+            .collect();
+
+        group.bench_with_input(BenchmarkId::new("pixels", name), &pixels, |b, pixels| {
+            b.iter(|| {
+                output.clear();
+                qoi::encode(width, height, pixels.iter().copied(), &mut output).unwrap();
+                black_box(&output);
+            })
+        });
+        group.bench_with_input(BenchmarkId::new("rgba", name), &rgba, |b, rgba| {
+            b.iter(|| {
+                output.clear();
+                qoi::encode_rgba(width, height, rgba, &mut output).unwrap();
+                black_box(&output);
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, encode_runs);
+criterion_main!(benches);
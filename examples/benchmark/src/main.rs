@@ -3,7 +3,7 @@ use qoi::Pixel;
 use rayon::prelude::*;
 use std::ffi::{c_void, CString};
 use std::fs::File;
-use std::io::{BufWriter, Seek, SeekFrom};
+use std::io::{BufReader, BufWriter, Seek, SeekFrom};
 use std::num::NonZeroUsize;
 use std::os::raw::c_char;
 use std::path::PathBuf;
@@ -29,12 +29,17 @@ struct Results {
     file: PathBuf,
     png_size: usize,
     qoi_size: usize,
+    qoi_rs_size: usize,
+    draft_size: usize,
     image_decode_time: f64,
     image_encode_time: f64,
     qoi_c_encode_time: f64,
     qoi_c_decode_time: f64,
     qoi_rs_encode_time: f64,
+    rgba_encode_time: f64,
     qoi_rs_decode_time: f64,
+    draft_encode_time: f64,
+    draft_decode_time: f64,
 }
 
 fn main() {
@@ -111,12 +116,46 @@ fn main() {
             let qoi_rs_size = qoi::encode(
                 NonZeroUsize::new(w).unwrap(),
                 NonZeroUsize::new(h).unwrap(),
-                pixels.into_iter(),
+                pixels.iter().copied(),
                 writer,
             )
             .unwrap();
             let qoi_rs_encode_time = (Instant::now() - start).as_secs_f64();
-            assert_eq!(qoi_size, qoi_rs_size);
+
+            // Both encoders follow the spec, so they should produce identical files
+            let c_bytes = std::fs::read(c_file.to_str().unwrap()).unwrap();
+            let rs_bytes = std::fs::read(&rs_file).unwrap();
+            assert!(c_bytes == rs_bytes, "ENCODERS DISAGREE: {}", name);
+
+            // Encode the image straight from its RGBA bytes, which finds runs with SIMD
+            let start = Instant::now();
+            let mut rgba = Vec::new();
+            qoi::encode_rgba(
+                NonZeroUsize::new(w).unwrap(),
+                NonZeroUsize::new(h).unwrap(),
+                &pin,
+                &mut rgba,
+            )
+            .unwrap();
+            let rgba_encode_time = (Instant::now() - start).as_secs_f64();
+            assert!(rgba == rs_bytes, "RGBA ENCODER DISAGREES: {}", name);
+
+            // Encode the image in the draft format using the Rust QOI encoder
+            let start = Instant::now();
+            let mut draft = Vec::new();
+            let options = qoi::EncodeOptions {
+                legacy: true,
+                ..Default::default()
+            };
+            let draft_size = qoi::encode_with_options(
+                NonZeroUsize::new(w).unwrap(),
+                NonZeroUsize::new(h).unwrap(),
+                pixels.iter().copied(),
+                &mut draft,
+                options,
+            )
+            .unwrap();
+            let draft_encode_time = (Instant::now() - start).as_secs_f64();
 
             // Decode the image using the Rust QOI decoder
             let start = Instant::now();
@@ -126,59 +165,253 @@ fn main() {
             assert_eq!(hh, h);
             let qoi_rs_decode_time = (Instant::now() - start).as_secs_f64();
 
+            // Decode the draft image using the Rust QOI decoder
+            let start = Instant::now();
+            let decoder = qoi::Decoder::new().legacy(true);
+            let (ww, hh, _data, _) = decoder.decode_to_vec(&draft[..]).unwrap();
+            assert_eq!(ww, w);
+            assert_eq!(hh, h);
+            let draft_decode_time = (Instant::now() - start).as_secs_f64();
+
             Results {
                 file: file.to_path_buf(),
                 png_size,
                 qoi_size,
+                qoi_rs_size,
+                draft_size,
                 image_decode_time,
                 image_encode_time,
                 qoi_c_encode_time,
                 qoi_c_decode_time,
                 qoi_rs_encode_time,
+                rgba_encode_time,
                 qoi_rs_decode_time,
+                draft_encode_time,
+                draft_decode_time,
             }
         })
         .collect();
 
     let png_size = results.iter().map(|r| r.png_size).sum::<usize>() / results.len();
     let qoi_size = results.iter().map(|r| r.qoi_size).sum::<usize>() / results.len();
+    let qoi_rs_size = results.iter().map(|r| r.qoi_rs_size).sum::<usize>() / results.len();
+    let draft_size = results.iter().map(|r| r.draft_size).sum::<usize>() / results.len();
     let image_encode_time: f64 = results.iter().map(|r| r.image_encode_time).sum();
     let image_decode_time: f64 = results.iter().map(|r| r.image_decode_time).sum();
     let qoi_c_encode_time: f64 = results.iter().map(|r| r.qoi_c_encode_time).sum();
     let qoi_c_decode_time: f64 = results.iter().map(|r| r.qoi_c_decode_time).sum();
     let qoi_r_encode_time: f64 = results.iter().map(|r| r.qoi_rs_encode_time).sum();
     let qoi_r_decode_time: f64 = results.iter().map(|r| r.qoi_rs_decode_time).sum();
+    let rgba_encode_time: f64 = results.iter().map(|r| r.rgba_encode_time).sum();
+    let draft_encode_time: f64 = results.iter().map(|r| r.draft_encode_time).sum();
+    let draft_decode_time: f64 = results.iter().map(|r| r.draft_decode_time).sum();
 
     //for result in &results {
     //    println!("{:#?}", result);
     //}
 
-    let r = results.len() as f64;
+    let n = results.len() as f64;
 
+    // The Rust spec encoder's files are identical to the C encoder's, so only the
+    // draft format's sizes differ
     println!("AVERAGE FILE SIZE:");
     let p = (qoi_size as f64) / (png_size as f64);
+    let rp = (qoi_rs_size as f64) / (png_size as f64);
+    let dp = (draft_size as f64) / (png_size as f64);
     println!("\tpng ...... {} kb", png_size / 1000);
-    println!("\tqoi ...... {} kb ({:.2}x larger)", qoi_size / 1000, p);
+    println!("\tc ........ {} kb ({:.2}x larger)", qoi_size / 1000, p);
+    println!("\tspec ..... {} kb ({:.2}x larger)", qoi_rs_size / 1000, rp);
+    println!("\tdraft .... {} kb ({:.2}x larger)", draft_size / 1000, dp);
 
     println!("AVERAGE ENCODE TIME:");
-    let i = (image_encode_time / r) * 1000.0;
-    let c = (qoi_c_encode_time / r) * 1000.0;
-    let r = (qoi_r_encode_time / r) * 1000.0;
+    let i = (image_encode_time / n) * 1000.0;
+    let c = (qoi_c_encode_time / n) * 1000.0;
+    let r = (qoi_r_encode_time / n) * 1000.0;
+    let a = (rgba_encode_time / n) * 1000.0;
+    let d = (draft_encode_time / n) * 1000.0;
     let cp = image_encode_time / qoi_c_encode_time;
     let rp = image_encode_time / qoi_r_encode_time;
+    let ap = image_encode_time / rgba_encode_time;
+    let dp = image_encode_time / draft_encode_time;
     println!("\timage .... {:.2} ms", i);
     println!("\tc ........ {:.2} ms ({:.2}x faster)", c, cp);
-    println!("\trust ..... {:.2} ms ({:.2}x faster)", r, rp);
+    println!("\tspec ..... {:.2} ms ({:.2}x faster)", r, rp);
+    println!("\trgba ..... {:.2} ms ({:.2}x faster)", a, ap);
+    println!("\tdraft .... {:.2} ms ({:.2}x faster)", d, dp);
 
     println!("AVERAGE DECODE TIME:");
-    let i = (image_decode_time / r) * 1000.0;
-    let c = (qoi_c_decode_time / r) * 1000.0;
-    let r = (qoi_r_decode_time / r) * 1000.0;
+    let i = (image_decode_time / n) * 1000.0;
+    let c = (qoi_c_decode_time / n) * 1000.0;
+    let r = (qoi_r_decode_time / n) * 1000.0;
+    let d = (draft_decode_time / n) * 1000.0;
     let cp = image_decode_time / qoi_c_decode_time;
     let rp = image_decode_time / qoi_r_decode_time;
+    let dp = image_decode_time / draft_decode_time;
     println!("\timage .... {:.2} ms", i);
     println!("\tc ........ {:.2} ms ({:.2}x faster)", c, cp);
-    println!("\trust ..... {:.2} ms ({:.2}x faster)", r, rp);
+    println!("\tspec ..... {:.2} ms ({:.2}x faster)", r, rp);
+    println!("\tdraft .... {:.2} ms ({:.2}x faster)", d, dp);
+
+    bench_rgba8_runs();
+    bench_solid_encode();
+    bench_frame_encode();
+    bench_file_decode(&out_dir);
+}
+
+/// Compare decoding a run-heavy image to RGBA bytes with `decode_to_rgba8`, which
+/// writes runs out all at once, against converting it pixel by pixel.
+fn bench_rgba8_runs() {
+    // Wide horizontal bands, so nearly every pixel is part of a long run
+    let (w, h) = (2048, 2048);
+    let pixels = (0..w * h).map(|i| Pixel::rgb((i / w / 64) as u8 * 8, 128, 255));
+    let mut data = Vec::new();
+    qoi::encode(
+        NonZeroUsize::new(w).unwrap(),
+        NonZeroUsize::new(h).unwrap(),
+        pixels,
+        &mut data,
+    )
+    .unwrap();
+
+    let start = Instant::now();
+    let (_, _, fast) = qoi::decode_to_rgba8(&data[..]).unwrap();
+    let fast_time = (Instant::now() - start).as_secs_f64() * 1000.0;
+
+    let start = Instant::now();
+    let (_, _, pixels) = qoi::decode(&data[..]).unwrap();
+    let mut slow = Vec::with_capacity(w * h * 4);
+    for p in pixels {
+        slow.extend_from_slice(&<[u8; 4]>::from(p.unwrap()));
+    }
+    let slow_time = (Instant::now() - start).as_secs_f64() * 1000.0;
+    assert_eq!(fast, slow);
+
+    println!("RUN-HEAVY RGBA8 DECODE TIME:");
+    println!("\tpixels ... {:.2} ms", slow_time);
+    println!(
+        "\trgba8 .... {:.2} ms ({:.2}x faster)",
+        fast_time,
+        slow_time / fast_time
+    );
+}
+
+fn bench_solid_encode() {
+    // A single color, so the whole image is one long run
+    let (w, h) = (4096, 4096);
+    let width = NonZeroUsize::new(w).unwrap();
+    let height = NonZeroUsize::new(h).unwrap();
+    let color = Pixel::rgb(40, 80, 120);
+
+    let start = Instant::now();
+    let mut fast = Vec::new();
+    qoi::encode(width, height, std::iter::repeat_n(color, w * h), &mut fast).unwrap();
+    let fast_time = (Instant::now() - start).as_secs_f64() * 1000.0;
+
+    // The streaming encoder takes pixels one at a time, so can't count runs ahead
+    let start = Instant::now();
+    let mut slow = Vec::new();
+    let mut encoder = qoi::Encoder::new(width, height, qoi::Channels::Rgba, &mut slow).unwrap();
+    for _ in 0..w * h {
+        encoder.push(color).unwrap();
+    }
+    encoder.finish().unwrap();
+    let slow_time = (Instant::now() - start).as_secs_f64() * 1000.0;
+    assert_eq!(fast, slow);
+
+    println!("SOLID COLOR ENCODE TIME:");
+    println!("\tpixels ... {:.2} ms", slow_time);
+    println!(
+        "\truns ..... {:.2} ms ({:.2}x faster)",
+        fast_time,
+        slow_time / fast_time
+    );
+}
+
+fn bench_frame_encode() {
+    // A short clip of small frames, where the per-image setup matters the most
+    let (w, h) = (64, 48);
+    let width = NonZeroUsize::new(w).unwrap();
+    let height = NonZeroUsize::new(h).unwrap();
+    let frames: Vec<Vec<Pixel>> = (0..60)
+        .map(|f| {
+            (0..w * h)
+                .map(|i| Pixel::rgb((i % w + f) as u8, (i / w) as u8, (f * 4) as u8))
+                .collect()
+        })
+        .collect();
+
+    // Write each frame to an unbuffered file, where every write is a system call
+    let path = std::env::temp_dir().join("qoi-bench-frames.qoi");
+    let mut file = File::create(&path).unwrap();
+    let start = Instant::now();
+    for frame in &frames {
+        file.seek(SeekFrom::Start(0)).unwrap();
+        qoi::encode(width, height, frame.iter().copied(), &mut file).unwrap();
+    }
+    let fresh_time = (Instant::now() - start).as_secs_f64() * 1000.0;
+    let fresh = std::fs::read(&path).unwrap();
+
+    let mut file = File::create(&path).unwrap();
+    let mut state = qoi::EncoderState::new();
+    let start = Instant::now();
+    for frame in &frames {
+        file.seek(SeekFrom::Start(0)).unwrap();
+        qoi::encode_with_state(width, height, frame.iter().copied(), &mut file, &mut state)
+            .unwrap();
+    }
+    let reused_time = (Instant::now() - start).as_secs_f64() * 1000.0;
+    let reused = std::fs::read(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(fresh, reused);
+
+    println!("60 FRAME ENCODE TIME:");
+    println!("\tfresh state ... {:.3} ms", fresh_time);
+    println!("\treused state .. {:.3} ms", reused_time);
+}
+
+fn bench_file_decode(out_dir: &str) {
+    // Decode the files the Rust encoder wrote straight from unbuffered files, where each
+    // read from the file is a system call
+    let files: Vec<PathBuf> = std::fs::read_dir(out_dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| {
+            path.file_name()
+                .unwrap()
+                .to_string_lossy()
+                .starts_with("r_")
+        })
+        .collect();
+    let decode_all = |decoder: &qoi::Decoder, buffered: bool| {
+        let start = Instant::now();
+        for path in &files {
+            let file = File::open(path).unwrap();
+            let (_, _, pixels, _) = match buffered {
+                true => decoder.decode_to_vec(BufReader::new(file)).unwrap(),
+                false => decoder.decode_to_vec(file).unwrap(),
+            };
+            assert!(!pixels.is_empty());
+        }
+        (Instant::now() - start).as_secs_f64() * 1000.0 / files.len() as f64
+    };
+
+    // A 1-byte read buffer is the same as reading the file a byte at a time
+    let bytewise = decode_all(&qoi::Decoder::new().read_buffer(1), false);
+    let unbuffered = decode_all(&qoi::Decoder::new(), false);
+    let buffered = decode_all(&qoi::Decoder::new(), true);
+
+    println!("AVERAGE FILE DECODE TIME:");
+    println!("\tbytewise ..... {:.2} ms", bytewise);
+    println!(
+        "\tfile ......... {:.2} ms ({:.2}x faster)",
+        unbuffered,
+        bytewise / unbuffered
+    );
+    println!(
+        "\tbufreader .... {:.2} ms ({:.2}x faster)",
+        buffered,
+        bytewise / buffered
+    );
 }
 
 fn read_dir(dir: PathBuf, images: &mut Vec<PathBuf>) {
@@ -0,0 +1,32 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use qoi::{decode_chunks, Decoder};
+
+// Decode arbitrary bytes in every way the decoder can read them. Whatever the bytes are,
+// decoding has to end with either the image's pixels or an error, and never a panic.
+fuzz_target!(|data: &[u8]| {
+    for legacy in [false, true] {
+        let decoder = Decoder::new().legacy(legacy);
+        if let Ok((w, h, pixels)) = decoder.decode(data) {
+            let mut count = 0;
+            for p in pixels {
+                if p.is_err() {
+                    break;
+                }
+                count += 1;
+            }
+            assert!(count <= w * h);
+        }
+        let _ = decoder.decode_to_vec(data);
+        let _ = decoder.decode_until_eof(data);
+    }
+
+    if let Ok(chunks) = decode_chunks(data) {
+        for chunk in chunks {
+            if chunk.is_err() {
+                break;
+            }
+        }
+    }
+});
@@ -0,0 +1,302 @@
+use crate::{consts::*, decode, Channels, Encoder, Error, Header, Pixel, Pixels};
+use core::num::NonZeroUsize;
+use core::pin::Pin;
+use core::task::{ready, Context, Poll};
+use futures_core::Stream;
+use std::io::Read;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf};
+
+// Async versions of `decode` and `encode`, for decoding and encoding images over
+// tokio's I/O traits without tying up a thread while waiting on the stream. They
+// share the synchronous decoder and encoder, which read from and write to in-memory
+// buffers that are filled and drained asynchronously.
+
+/// How many bytes are read from the stream at a time.
+const READ_SIZE: usize = 8 * 1024;
+
+/// Decode the image encoded in the bytes provided by `input`, reading from it
+/// asynchronously. The return value is the image's header, and a stream of its pixels.
+/// This otherwise behaves just like [`decode`].
+pub async fn decode_async<R>(mut input: R) -> Result<(Header, AsyncPixels<R>), Error>
+where
+    R: AsyncRead + Unpin,
+{
+    // Read as much of the header as there is, and let the decoder check it
+    let mut header = [0; HEADER_SIZE];
+    let mut len = 0;
+    while len < HEADER_SIZE {
+        match input.read(&mut header[len..]).await? {
+            0 => break,
+            n => len += n,
+        }
+    }
+    let feed = Feed {
+        data: header[..len].to_vec(),
+        pos: 0,
+        eof: len < HEADER_SIZE,
+    };
+    let (_, _, pixels) = decode(feed)?;
+    let mut tail = [0; 8];
+    tail.copy_from_slice(&header[HEADER_SIZE - 8..]);
+    let eof = pixels.get_ref().eof;
+    let pixels = AsyncPixels {
+        input,
+        pixels,
+        eof,
+        failed: false,
+        pending: None,
+        tail,
+    };
+    Ok((pixels.header(), pixels))
+}
+
+/// A stream that parses pixels from the encoded image's data block, reading it
+/// asynchronously. See [`decode_async`].
+///
+/// Like [`Pixels`], this yields `Result` values that will carry an error if the
+/// parser fails, and ends once one is returned.
+pub struct AsyncPixels<R> {
+    input: R,
+    pixels: Pixels<Feed>,
+    eof: bool,
+    failed: bool,
+    pending: Option<Error>,
+    tail: [u8; 8],
+}
+
+impl<R> AsyncPixels<R> {
+    /// Get the information stored in the image's header.
+    #[inline]
+    pub fn header(&self) -> Header {
+        self.pixels.header()
+    }
+
+    /// The amount of pixels left to be decoded.
+    #[inline]
+    pub fn remaining(&self) -> usize {
+        if self.failed || self.pending.is_some() {
+            0
+        } else {
+            self.pixels.remaining()
+        }
+    }
+}
+
+impl<R> Stream for AsyncPixels<R>
+where
+    R: AsyncRead + Unpin,
+{
+    type Item = Result<Pixel, Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        loop {
+            if this.remaining() == 0 && this.pending.is_none() {
+                return Poll::Ready(None);
+            }
+
+            // Make sure the decoder won't run out of bytes partway through the next
+            // pixel, unless the stream itself ends there. If there's an error waiting to
+            // be returned, the stream is only read from once more, to see if it ends
+            // right after what's been read, like the synchronous decoder does.
+            while !this.eof
+                && (this.pending.is_some()
+                    || this.pixels.get_ref().len() < this.pixels.bytes_needed())
+            {
+                let mut bytes = [0; READ_SIZE];
+                let mut buf = ReadBuf::new(&mut bytes);
+                if let Err(err) = ready!(Pin::new(&mut this.input).poll_read(cx, &mut buf)) {
+                    this.pending = None;
+                    this.failed = true;
+                    return Poll::Ready(Some(Err(err.into())));
+                }
+
+                // Remember the last few bytes, so we can tell what the stream ended with
+                let bytes = buf.filled();
+                let n = bytes.len().min(8);
+                this.tail.rotate_left(n);
+                this.tail[8 - n..].copy_from_slice(&bytes[bytes.len() - n..]);
+
+                let feed = this.pixels.get_mut();
+                if bytes.is_empty() {
+                    this.eof = true;
+                    feed.eof = true;
+                } else if this.pending.is_none() {
+                    feed.data.drain(..feed.pos);
+                    feed.pos = 0;
+                    feed.data.extend_from_slice(bytes);
+                } else {
+                    break;
+                }
+            }
+
+            // Now that we know if the stream ended there, see if it was in the other format
+            if let Some(err) = this.pending.take() {
+                this.failed = true;
+                let legacy = this.eof && this.tail[4..] == LEGACY_END_MARKER;
+                return Poll::Ready(Some(Err(match legacy {
+                    true => Error::UnsupportedQoiVersion,
+                    false => err,
+                })));
+            }
+
+            // These errors usually mean the data is in the other format, but the decoder
+            // can only tell that from what it's been fed, which is all of the stream once
+            // it's ended
+            match this.pixels.next() {
+                Some(Err(err @ (Error::TooMuchData | Error::Truncated { .. }))) if !this.eof => {
+                    this.pending = Some(err);
+                }
+                result => return Poll::Ready(result),
+            }
+        }
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        // If an error ends the stream early, it's the last item that's yielded
+        let len = self.remaining() + usize::from(self.pending.is_some());
+        (len.min(1), Some(len))
+    }
+}
+
+/// Bytes that have been read from an async stream, for the decoder to read. Until the
+/// stream has ended, running out of bytes is reported as an error instead of as the end
+/// of the data, so the decoder never mistakes the end of what's been read for the end of
+/// the stream.
+#[derive(Default)]
+struct Feed {
+    data: Vec<u8>,
+    pos: usize,
+    eof: bool,
+}
+
+impl Feed {
+    /// The amount of bytes that haven't been read yet.
+    #[inline]
+    fn len(&self) -> usize {
+        self.data.len() - self.pos
+    }
+}
+
+impl Read for Feed {
+    #[inline]
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.len() == 0 && !self.eof && !buf.is_empty() {
+            return Err(std::io::ErrorKind::WouldBlock.into());
+        }
+        let n = buf.len().min(self.len());
+        buf[..n].copy_from_slice(&self.data[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+/// Encodes the pixels supplied by the `pixels` iterator into the `output` stream, writing
+/// to it asynchronously. The iterator is expected to have `width * height` pixels in it.
+/// Returns the size of the encoded data. This produces the same data as
+/// [`encode`](crate::encode).
+pub async fn encode_async<I, W>(
+    width: NonZeroUsize,
+    height: NonZeroUsize,
+    pixels: I,
+    mut output: W,
+) -> Result<usize, Error>
+where
+    I: IntoIterator<Item = Pixel>,
+    W: AsyncWrite + Unpin,
+{
+    // Encode into a buffer, and write it out whenever it fills up
+    let mut buf = Vec::with_capacity(READ_SIZE);
+    let mut encoder = Encoder::new(width, height, Channels::Rgba, &mut buf)?;
+    let mut pixels = pixels.into_iter();
+    let num_pixels = width.get() * height.get();
+    for count in 0..num_pixels {
+        let px = pixels.next().ok_or(Error::IteratorEmpty {
+            expected: num_pixels,
+            got: count,
+        })?;
+        encoder.push(px)?;
+
+        let buf = encoder.get_mut();
+        if buf.len() >= READ_SIZE {
+            output.write_all(buf).await?;
+            buf.clear();
+        }
+    }
+    let num_bytes = encoder.finish()?;
+    output.write_all(&buf).await?;
+    Ok(num_bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{encode_with_options, EncodeOptions};
+    use core::future::{poll_fn, Future};
+    use core::task::Waker;
+    use tokio::io::AsyncReadExt;
+
+    /// Run a future to completion, for readers that are always ready.
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let mut future = core::pin::pin!(future);
+        let mut cx = Context::from_waker(Waker::noop());
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                return output;
+            }
+        }
+    }
+
+    fn next<R>(pixels: &mut AsyncPixels<R>) -> Option<Result<Pixel, Error>>
+    where
+        R: AsyncRead + Unpin,
+    {
+        block_on(poll_fn(|cx| Pin::new(&mut *pixels).poll_next(cx)))
+    }
+
+    fn collect<R>(mut pixels: AsyncPixels<R>) -> Result<Vec<Pixel>, Error>
+    where
+        R: AsyncRead + Unpin,
+    {
+        core::iter::from_fn(|| next(&mut pixels)).collect()
+    }
+
+    /// Encode `pixels` as a single row, in the format `legacy` says.
+    fn encode_row(pixels: &[Pixel], legacy: bool) -> Vec<u8> {
+        let width = NonZeroUsize::new(pixels.len()).unwrap();
+        let height = NonZeroUsize::new(1).unwrap();
+        let mut data = Vec::new();
+        let options = EncodeOptions {
+            legacy,
+            ..Default::default()
+        };
+        encode_with_options(width, height, pixels.iter().copied(), &mut data, options).unwrap();
+        data
+    }
+
+    #[test]
+    fn decodes_like_the_sync_decoder() {
+        let pixels: Vec<Pixel> = (0..200).map(|i| Pixel::rgba(i, i / 3, 7, 255)).collect();
+        let data = encode_row(&pixels, false);
+        let (header, stream) = block_on(decode_async(&data[..])).unwrap();
+        assert_eq!(header.width, 200);
+        assert_eq!(collect(stream).unwrap(), pixels);
+
+        let legacy = encode_row(&pixels, true);
+        let (_, stream) = block_on(decode_async(&legacy[..])).unwrap();
+        assert!(matches!(collect(stream), Err(Error::UnsupportedQoiVersion)));
+    }
+
+    #[test]
+    fn invalid_data_doesnt_drain_endless_streams() {
+        // A run of 62 pixels in a 1x1 image, followed by a stream that never ends
+        let data = encode_row(&[Pixel::rgb(1, 2, 3)], false);
+        let mut data = data[..HEADER_SIZE].to_vec();
+        data.push(OP_RUN | 61);
+        let input = AsyncReadExt::chain(&data[..], tokio::io::repeat(OP_RGB));
+        let (_, mut stream) = block_on(decode_async(input)).unwrap();
+        assert!(matches!(next(&mut stream), Some(Err(Error::TooMuchData))));
+        assert!(next(&mut stream).is_none());
+    }
+}
@@ -0,0 +1,108 @@
+// A command line tool for converting images to and from QOI, using the `image` crate to
+// read and write every other format.
+
+use image::{DynamicImage, ImageFormat};
+use qoi::{Channels, Colorspace, EncodeOptions, Pixel, QoiDecoder};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::num::NonZeroUsize;
+use std::process::ExitCode;
+
+const USAGE: &str = "\
+Usage:
+    qoi encode <input> <output.qoi> [--channels 3|4] [--colorspace srgb|linear]
+    qoi decode <input.qoi> <output>
+
+Encoding reads any image format the `image` crate supports. By default, the image is
+marked as having 4 channels if it has an alpha channel and 3 if it doesn't, and as
+being in the sRGB colorspace. Decoding writes whichever format the output's file
+extension is for.";
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let result = match args.as_slice() {
+        ["encode", input, output, flags @ ..] => match parse_flags(flags) {
+            Some(flags) => encode(input, output, flags),
+            None => return usage(),
+        },
+        ["decode", input, output] => decode(input, output),
+        _ => return usage(),
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("error: {}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn usage() -> ExitCode {
+    eprintln!("{}", USAGE);
+    ExitCode::from(2)
+}
+
+/// The header fields to encode the image with, where `None` means to pick the default.
+#[derive(Default)]
+struct Flags {
+    channels: Option<Channels>,
+    colorspace: Option<Colorspace>,
+}
+
+/// Parse the flags after the encode command's paths, returning `None` if any are invalid.
+fn parse_flags(mut args: &[&str]) -> Option<Flags> {
+    let mut flags = Flags::default();
+    while let [flag, value, rest @ ..] = args {
+        match *flag {
+            "--channels" => {
+                flags.channels = Some(Channels::try_from(value.parse::<u8>().ok()?).ok()?)
+            }
+            "--colorspace" => {
+                flags.colorspace = Some(match *value {
+                    "srgb" => Colorspace::Srgb,
+                    "linear" => Colorspace::Linear,
+                    _ => return None,
+                })
+            }
+            _ => return None,
+        }
+        args = rest;
+    }
+    args.is_empty().then_some(flags)
+}
+
+/// Encode the image at `input` into a QOI file at `output`.
+fn encode(input: &str, output: &str, flags: Flags) -> Result<()> {
+    let img = image::open(input)?;
+    let channels = flags.channels.unwrap_or(match img.color().has_alpha() {
+        true => Channels::Rgba,
+        false => Channels::Rgb,
+    });
+    let img = img.to_rgba8();
+    let width = NonZeroUsize::new(img.width() as usize).ok_or(qoi::Error::NoImageSize)?;
+    let height = NonZeroUsize::new(img.height() as usize).ok_or(qoi::Error::NoImageSize)?;
+    let options = EncodeOptions {
+        channels,
+        colorspace: flags.colorspace.unwrap_or_default(),
+        ..Default::default()
+    };
+
+    let mut file = BufWriter::new(File::create(output)?);
+    let pixels = img.pixels().map(|p| Pixel::from(p.0));
+    qoi::encode_with_options(width, height, pixels, &mut file, options)?;
+    file.flush()?;
+    Ok(())
+}
+
+/// Decode the QOI file at `input` into an image at `output`, in the format its file
+/// extension is for.
+fn decode(input: &str, output: &str) -> Result<()> {
+    let format = ImageFormat::from_path(output)?;
+    let decoder = QoiDecoder::new(BufReader::new(File::open(input)?))?;
+    let img = DynamicImage::from_decoder(decoder)?;
+    img.save_with_format(output, format)?;
+    Ok(())
+}
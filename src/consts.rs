@@ -2,6 +2,38 @@
 pub const MAGIC: u32 =
     ((b'q' as u32) << 24) | ((b'o' as u32) << 16) | ((b'i' as u32) << 8) | (b'f' as u32);
 
+pub const HEADER_SIZE: usize = 14;
+
+// The most pixels to allocate room for before any of an image's pixels have been decoded
+pub const MAX_PREALLOC: usize = 1 << 20;
+
+// The end marker of the released QOI specification
+pub const END_MARKER: [u8; 8] = [0, 0, 0, 0, 0, 0, 0, 1];
+
+// The end marker of the pre-release draft format
+pub const LEGACY_END_MARKER: [u8; 4] = [0, 0, 0, 0];
+
+// A crate-specific flag in the header's colorspace byte, marking that every odd row of
+// the image is stored right-to-left
+pub const SERPENTINE_FLAG: u8 = 0x80;
+
+// Opcodes of the released QOI specification
+pub const OP_INDEX: u8 = 0x00;
+pub const OP_DIFF: u8 = 0x40;
+pub const OP_LUMA: u8 = 0x80;
+pub const OP_RUN: u8 = 0xc0;
+pub const OP_RGB: u8 = 0xfe;
+pub const OP_RGBA: u8 = 0xff;
+
+// The longest run a single OP_RUN chunk can hold, since its 6-bit length is stored with
+// a bias of -1, and the lengths 63 and 64 would collide with OP_RGB and OP_RGBA
+pub const MAX_RUN: u8 = 62;
+
+// The longest run a single RUN_16 chunk of the draft format can hold, which is its
+// 13-bit length plus the 33 that RUN_8 chunks already cover
+pub const LEGACY_MAX_RUN: u16 = 0x2020;
+
+// Opcodes of the pre-release draft format
 pub const INDEX: u8 = 0x0;
 pub const RUN_8: u8 = 0x40;
 pub const RUN_16: u8 = 0x60;
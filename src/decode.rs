@@ -1,227 +1,1839 @@
-use crate::{consts::*, Error, Pixel};
-use std::fs::File;
-use std::io::{BufReader, Read};
-use std::mem::MaybeUninit;
-use std::path::Path;
+use crate::io::{ErrorKind, Read, Write};
+use crate::{consts::*, Downsampler, Encoder, Error, Pixel};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::iter::FusedIterator;
+use core::num::NonZeroUsize;
+#[cfg(feature = "std")]
+use std::{fs::File, io::BufReader, path::Path};
 
+/// Read `N` bytes that start `offset` bytes into the stream.
 #[inline]
-fn read<R: Read, const N: usize>(input: &mut R) -> Result<[u8; N], Error> {
-    let mut bytes: [u8; N] = unsafe { MaybeUninit::uninit().assume_init() };
-    input.read_exact(&mut bytes)?;
+fn read<R: Read, const N: usize>(input: &mut R, offset: usize) -> Result<[u8; N], Error> {
+    let mut bytes = [0; N];
+    let mut filled = 0;
+    while filled < N {
+        match input.read(&mut bytes[filled..]) {
+            Ok(0) => {
+                return Err(Error::UnexpectedEof {
+                    offset: offset + filled,
+                })
+            }
+            Ok(n) => filled += n,
+            Err(err) if err.kind() == ErrorKind::Interrupted => {}
+            Err(err) => return Err(err.into()),
+        }
+    }
     Ok(bytes)
 }
 
-#[inline]
-fn read_u8<R: Read>(input: &mut R) -> Result<u8, Error> {
-    Ok(read::<R, 1>(input)?[0])
+#[inline]
+fn read_u32<R: Read>(input: &mut R, offset: usize) -> Result<u32, Error> {
+    Ok(u32::from_be_bytes(read::<R, 4>(input, offset)?))
+}
+
+/// How many of an image's `n` pixels to allocate room for before decoding them. The
+/// header's size can't be trusted until the pixels are actually there, so a corrupt or
+/// malicious one mustn't be able to make us allocate a huge buffer up front.
+#[inline]
+pub(crate) fn initial_capacity(n: usize) -> usize {
+    n.min(MAX_PREALLOC)
+}
+
+/// The information stored in an image's header.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct Header {
+    /// The width of the image.
+    pub width: usize,
+
+    /// The height of the image.
+    pub height: usize,
+
+    /// The channels the image was encoded from. This is only informative, since pixels
+    /// are always decoded with an alpha channel.
+    pub channels: Channels,
+
+    /// The color space of the image. This is only informative, since pixels are never
+    /// converted.
+    pub colorspace: Colorspace,
+}
+
+/// The channels an image is marked as having in its header.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub enum Channels {
+    /// Red, green, and blue, with every pixel fully opaque.
+    Rgb = 3,
+
+    /// Red, green, blue, and alpha.
+    #[default]
+    Rgba = 4,
+}
+
+impl TryFrom<u8> for Channels {
+    type Error = Error;
+
+    /// Convert a header's channel count, failing with [`Error::InvalidChannels`] if it
+    /// isn't 3 or 4.
+    #[inline]
+    fn try_from(channels: u8) -> Result<Self, Error> {
+        match channels {
+            3 => Ok(Self::Rgb),
+            4 => Ok(Self::Rgba),
+            _ => Err(Error::InvalidChannels(channels)),
+        }
+    }
+}
+
+impl From<Channels> for u8 {
+    #[inline]
+    fn from(channels: Channels) -> Self {
+        channels as u8
+    }
+}
+
+/// The color space an image is marked as having in its header.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub enum Colorspace {
+    /// sRGB color channels, with a linear alpha channel.
+    #[default]
+    Srgb = 0,
+
+    /// All channels linear.
+    Linear = 1,
+}
+
+impl TryFrom<u8> for Colorspace {
+    type Error = Error;
+
+    /// Convert a header's colorspace byte, failing with [`Error::InvalidColorspace`] if
+    /// it isn't 0 or 1.
+    #[inline]
+    fn try_from(colorspace: u8) -> Result<Self, Error> {
+        match colorspace {
+            0 => Ok(Self::Srgb),
+            1 => Ok(Self::Linear),
+            _ => Err(Error::InvalidColorspace(colorspace)),
+        }
+    }
+}
+
+impl From<Colorspace> for u8 {
+    #[inline]
+    fn from(colorspace: Colorspace) -> Self {
+        colorspace as u8
+    }
+}
+
+/// Read the image's header, without decoding any of its pixels. The input is left at
+/// the start of the image's data block.
+pub fn decode_header<R>(input: &mut R) -> Result<Header, Error>
+where
+    R: Read,
+{
+    read_header(input, false).map(|(header, _)| header)
+}
+
+/// Read just the image's header and return its width and height, without touching any
+/// of its pixel data. The header is checked the same way [`decode`] checks it.
+#[inline]
+pub fn decode_dimensions<R>(mut input: R) -> Result<(usize, usize), Error>
+where
+    R: Read,
+{
+    let header = decode_header(&mut input)?;
+    Ok((header.width, header.height))
+}
+
+/// Read the image's header, along with the raw color space byte which may also
+/// hold crate-specific flags. If `lenient`, invalid channels and colorspaces are
+/// replaced with the defaults instead of failing.
+fn read_header<R>(input: &mut R, lenient: bool) -> Result<(Header, u8), Error>
+where
+    R: Read,
+{
+    // Parse the magic filetype marker.
+    let magic = read_u32(input, 0)?;
+    if magic != MAGIC {
+        return Err(Error::InvalidFileTypeMarker(magic.to_be_bytes()));
+    }
+
+    // Parse the image size. The rest of the header is read all at once, since the reader
+    // might not be buffered, and the pixels are only buffered after this.
+    let [w0, w1, w2, w3, h0, h1, h2, h3, channels, color_space] = read::<R, 10>(input, 4)?;
+    let width = u32::from_be_bytes([w0, w1, w2, w3]) as usize;
+    let height = u32::from_be_bytes([h0, h1, h2, h3]) as usize;
+    if width == 0 || height == 0 {
+        return Err(Error::NoImageSize);
+    }
+    if width.checked_mul(height).is_none() {
+        return Err(Error::ImageTooLarge { width, height });
+    }
+
+    let mut channels = Channels::try_from(channels);
+    let mut colorspace = Colorspace::try_from(color_space & !SERPENTINE_FLAG);
+    if lenient {
+        channels = Ok(channels.unwrap_or_default());
+        colorspace = Ok(colorspace.unwrap_or_default());
+    }
+    let header = Header {
+        width,
+        height,
+        channels: channels?,
+        colorspace: colorspace?,
+    };
+    Ok((header, color_space))
+}
+
+/// Decode the image, filling `output` with the image's pixels.
+#[inline]
+pub fn decode_into_vec<R>(input: R, output: &mut Vec<Pixel>) -> Result<(usize, usize), Error>
+where
+    R: Read,
+{
+    let (w, h, pixels) = decode(input)?;
+    output.clear();
+    output.reserve(initial_capacity(w * h));
+    for p in pixels {
+        output.push(p?);
+    }
+    Ok((w, h))
+}
+
+/// Decode the image, filling `output` with the image's pixels with its rows in
+/// bottom-to-top order, like OpenGL textures expect.
+#[inline]
+pub fn decode_into_vec_flipped<R>(
+    input: R,
+    output: &mut Vec<Pixel>,
+) -> Result<(usize, usize), Error>
+where
+    R: Read,
+{
+    let (w, h) = decode_into_vec(input, output)?;
+    flip_rows(output, w);
+    Ok((w, h))
+}
+
+/// Reverse the order of the rows of an image that is `width` pixels wide.
+fn flip_rows(pixels: &mut [Pixel], width: usize) {
+    let height = pixels.len() / width;
+    for y in 0..height / 2 {
+        let (top, bottom) = pixels.split_at_mut((height - 1 - y) * width);
+        top[y * width..(y + 1) * width].swap_with_slice(&mut bottom[..width]);
+    }
+}
+
+/// Decode the image into the start of `output`, which must have room for all of the
+/// image's pixels, or [`Error::BufferTooSmall`] is returned before any are decoded.
+/// Anything in `output` past the image's pixels is left untouched.
+pub fn decode_into_slice<R>(input: R, output: &mut [Pixel]) -> Result<(usize, usize), Error>
+where
+    R: Read,
+{
+    let (w, h, pixels) = decode(input)?;
+    if output.len() < w * h {
+        return Err(Error::BufferTooSmall {
+            needed: w * h,
+            got: output.len(),
+        });
+    }
+    for (dst, p) in output.iter_mut().zip(pixels) {
+        *dst = p?;
+    }
+    Ok((w, h))
+}
+
+/// Decode the image file.
+#[cfg(feature = "std")]
+#[inline]
+pub fn decode_file<F>(path: F) -> Result<(usize, usize, Pixels<BufReader<File>>), Error>
+where
+    F: AsRef<Path>,
+{
+    decode(BufReader::new(File::open(path)?))
+}
+
+/// Decode the image file, filling `output` with the image's pixels.
+#[cfg(feature = "std")]
+#[inline]
+pub fn decode_file_into_vec<F>(path: F, output: &mut Vec<Pixel>) -> Result<(usize, usize), Error>
+where
+    F: AsRef<Path>,
+{
+    decode_into_vec(File::open(path)?, output)
+}
+
+/// Decode the image file into a new file at `out_path`, as interleaved RGBA bytes. The
+/// output file ends up `width * height * 4` bytes long, and is memory-mapped a piece at a
+/// time, so pixels are written straight into it and the decoded image is never held in
+/// memory all at once. Returns the image's `width` and `height`.
+///
+/// The file only grows as pixels are decoded, so a header that claims a huge image can't
+/// make a huge file unless the data for it is there too. An image too large to count its
+/// bytes in a `usize` fails with [`Error::ImageTooLarge`].
+#[cfg(feature = "memmap2")]
+pub fn decode_file_to_mmap<F, O>(path: F, out_path: O) -> Result<(usize, usize), Error>
+where
+    F: AsRef<Path>,
+    O: AsRef<Path>,
+{
+    let (w, h, mut pixels) = decode_file(path)?;
+    let len = w
+        .checked_mul(h)
+        .and_then(|n| n.checked_mul(4))
+        .ok_or(Error::ImageTooLarge {
+            width: w,
+            height: h,
+        })?;
+
+    let file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(out_path)?;
+
+    // Grow the file by a window at a time, and map just that window to decode into. The
+    // window is a multiple of the page size, so each one can be mapped at its offset.
+    let mut offset = 0;
+    while offset < len {
+        let window = (len - offset).min(MAX_PREALLOC * 4);
+        file.set_len((offset + window) as u64)?;
+
+        // SAFETY: we just created the file, and nothing else should be modifying it while
+        // it's mapped. If another process does, the worst outcome is garbage pixel data.
+        let mut map = unsafe {
+            memmap2::MmapOptions::new()
+                .offset(offset as u64)
+                .len(window)
+                .map_mut(&file)?
+        };
+        for (bytes, p) in map.chunks_exact_mut(4).zip(&mut pixels) {
+            bytes.copy_from_slice(&<[u8; 4]>::from(p?));
+        }
+        map.flush()?;
+        offset += window;
+    }
+    Ok((w, h))
+}
+
+/// Decode the image, failing with [`Error::DimensionMismatch`] before decoding any
+/// pixels if its size isn't `expected_width` by `expected_height`.
+pub fn decode_expecting<R>(
+    input: R,
+    expected_width: usize,
+    expected_height: usize,
+) -> Result<Vec<Pixel>, Error>
+where
+    R: Read,
+{
+    let (w, h, pixels) = decode(input)?;
+    if (w, h) != (expected_width, expected_height) {
+        return Err(Error::DimensionMismatch {
+            expected: (expected_width, expected_height),
+            found: (w, h),
+        });
+    }
+    let mut output = Vec::with_capacity(initial_capacity(w * h));
+    for p in pixels {
+        output.push(p?);
+    }
+    Ok(output)
+}
+
+/// Decode the whole image, making sure it's exactly what its header says it is: the
+/// data block has to produce exactly `width * height` pixels and be followed by the end
+/// marker, or this fails with [`Error::Truncated`], [`Error::TooMuchData`], or
+/// [`Error::MissingEndMarker`]. This is the safest way to decode untrusted input.
+pub fn decode_strict<R>(input: R) -> Result<(Header, Vec<Pixel>), Error>
+where
+    R: Read,
+{
+    let (w, h, mut pixels) = decode(input)?;
+    let mut output = Vec::with_capacity(initial_capacity(w * h));
+    for p in &mut pixels {
+        match p {
+            Ok(px) => output.push(px),
+            Err(Error::UnexpectedEof { .. }) => {
+                return Err(Error::Truncated {
+                    expected: w * h,
+                    got: output.len(),
+                })
+            }
+            Err(err) => return Err(err),
+        }
+    }
+    pixels.verify_end_marker()?;
+    Ok((pixels.header(), output))
+}
+
+/// Decode an image with a height of 1, like one written by
+/// [`encode_strip`](crate::encode_strip), returning its row of pixels. Fails with
+/// [`Error::DimensionMismatch`] before decoding any pixels if the image has more
+/// than one row.
+pub fn decode_strip<R>(input: R) -> Result<Vec<Pixel>, Error>
+where
+    R: Read,
+{
+    let (w, h, pixels) = decode(input)?;
+    if h != 1 {
+        return Err(Error::DimensionMismatch {
+            expected: (w, 1),
+            found: (w, h),
+        });
+    }
+    pixels.collect()
+}
+
+/// Decode only the rows `y0..y1` of the image, returning their pixels. The rows
+/// before `y0` are still parsed (the decoder's state depends on them), but they
+/// are skipped over without being stored, and decoding stops after row `y1 - 1`.
+///
+/// The range is clamped to the image's height.
+pub fn decode_row_range<R>(input: R, y0: usize, y1: usize) -> Result<Vec<Pixel>, Error>
+where
+    R: Read,
+{
+    let (w, h, mut pixels) = decode(input)?;
+    let y1 = y1.min(h);
+    let y0 = y0.min(y1);
+
+    // Skip past the rows we don't want
+    if y0 > 0 {
+        if let Some(Err(err)) = pixels.nth(y0 * w - 1) {
+            return Err(err);
+        }
+    }
+
+    let mut output = Vec::with_capacity(initial_capacity((y1 - y0) * w));
+    for p in pixels.take((y1 - y0) * w) {
+        output.push(p?);
+    }
+    Ok(output)
+}
+
+/// Decode the image into four separate planes of red, green, blue, and alpha values,
+/// each containing `width * height` values in row-major order.
+pub fn decode_planar<R>(input: R) -> Result<(usize, usize, [Vec<u8>; 4]), Error>
+where
+    R: Read,
+{
+    let (w, h, pixels) = decode(input)?;
+    let mut planes: [Vec<u8>; 4] = Default::default();
+    for plane in &mut planes {
+        plane.reserve_exact(initial_capacity(w * h));
+    }
+    for p in pixels {
+        let p = p?;
+        planes[0].push(p.r);
+        planes[1].push(p.g);
+        planes[2].push(p.b);
+        planes[3].push(p.a);
+    }
+    Ok((w, h, planes))
+}
+
+/// Decode the image, shrinking it on the fly so that neither its width nor its height
+/// exceed `max_dim`. The image is shrunk by the smallest whole-number factor that fits,
+/// with each output pixel being the average of the block of pixels it covers. Returns
+/// the shrunken image's `width`, `height`, and pixels.
+///
+/// The full image is never stored, so memory use is bounded by the size of the output.
+pub fn decode_downsampled<R>(input: R, max_dim: usize) -> Result<(usize, usize, Vec<Pixel>), Error>
+where
+    R: Read,
+{
+    let (w, h, pixels) = decode(input)?;
+    let mut downsampler = Downsampler::new(w, h, max_dim);
+    for p in pixels {
+        downsampler.push(p?);
+    }
+    Ok(downsampler.finish())
+}
+
+/// Decode the encoded image in `bytes`, returning its `width`, `height`, and its pixels
+/// as interleaved RGBA bytes. This is the same as [`decode_to_rgba8`], but it only
+/// takes and returns types that are cheap to pass across an FFI boundary, like the one
+/// `wasm-bindgen` makes between Rust and JavaScript.
+#[inline]
+pub fn decode_all(bytes: &[u8]) -> Result<(usize, usize, Vec<u8>), Error> {
+    decode_to_rgba8(bytes)
+}
+
+/// Decode the image into interleaved RGBA bytes. The output has `width * height * 4`
+/// bytes in it. Runs of pixels are written out all at once, which is vectorized when
+/// the `simd` feature is enabled.
+pub fn decode_to_rgba8<R>(input: R) -> Result<(usize, usize, Vec<u8>), Error>
+where
+    R: Read,
+{
+    let mut output = Vec::new();
+    let (w, h) = decode_into_rgba(input, &mut output)?;
+    Ok((w, h, output))
+}
+
+/// Decode the image, filling `output` with its pixels as interleaved RGBA bytes, like
+/// [`decode_to_rgba8`] does. To get RGB bytes instead, use [`decode_to_rgb8_forced`].
+pub fn decode_into_rgba<R>(input: R, output: &mut Vec<u8>) -> Result<(usize, usize), Error>
+where
+    R: Read,
+{
+    let (w, h, mut pixels) = decode(input)?;
+    output.clear();
+    output.reserve(initial_capacity(w * h) * 4);
+    while let Some(p) = pixels.next() {
+        let bytes: [u8; 4] = p?.into();
+        output.extend_from_slice(&bytes);
+
+        // Reversed rows come out of a buffer, so only runs in stored order can be taken
+        if !pixels.serpentine {
+            let n = pixels.take_run()?;
+            fill_run(output, bytes, n);
+        }
+    }
+    Ok((w, h))
+}
+
+/// Decode the image file, filling `output` with its pixels as interleaved RGBA bytes.
+#[cfg(feature = "std")]
+#[inline]
+pub fn decode_file_into_rgba<F>(path: F, output: &mut Vec<u8>) -> Result<(usize, usize), Error>
+where
+    F: AsRef<Path>,
+{
+    decode_into_rgba(File::open(path)?, output)
+}
+
+/// Append `n` copies of a pixel's bytes to `output`.
+#[inline]
+fn fill_run(output: &mut Vec<u8>, bytes: [u8; 4], n: usize) {
+    output.reserve(n * 4);
+
+    // Broadcast the pixel across a vector, and write out 8 copies at a time
+    #[cfg(feature = "simd")]
+    let n = {
+        let lanes = wide::u32x8::splat(u32::from_ne_bytes(bytes));
+        let chunk: [u8; 32] = bytemuck::cast(lanes);
+        for _ in 0..n / 8 {
+            output.extend_from_slice(&chunk);
+        }
+        n % 8
+    };
+
+    for _ in 0..n {
+        output.extend_from_slice(&bytes);
+    }
+}
+
+/// Decode the image into interleaved RGB bytes, always dropping the alpha channel
+/// regardless of how many channels the image has. The output has `width * height * 3`
+/// bytes in it.
+pub fn decode_to_rgb8_forced<R>(input: R) -> Result<(usize, usize, Vec<u8>), Error>
+where
+    R: Read,
+{
+    let (w, h, pixels) = decode(input)?;
+    let mut output = Vec::with_capacity(initial_capacity(w * h) * 3);
+    for p in pixels {
+        let p = p?;
+        output.extend_from_slice(&p.to_rgb_array());
+    }
+    Ok((w, h, output))
+}
+
+/// Decode the image into the layout of a Cairo `ARGB32` image surface, which GTK also
+/// uses: each pixel is a native-endian 32-bit integer holding premultiplied alpha, red,
+/// green, and blue, from the most to least significant byte. Returns the image's
+/// `width`, `height`, the stride of each row in bytes, and the pixel data.
+pub fn decode_to_cairo_argb32<R>(input: R) -> Result<(usize, usize, usize, Vec<u8>), Error>
+where
+    R: Read,
+{
+    let (w, h, pixels) = decode(input)?;
+
+    // Rows of 32-bit pixels are always aligned to 4 bytes, so there's no padding
+    let stride = w * 4;
+    let mut output = Vec::with_capacity(initial_capacity(w * h) * 4);
+    for p in pixels {
+        let p = p?;
+        let a = p.a as u32;
+        let premultiply = |c: u8| (c as u32 * a + 127) / 255;
+        let argb = a << 24 | premultiply(p.r) << 16 | premultiply(p.g) << 8 | premultiply(p.b);
+        output.extend_from_slice(&argb.to_ne_bytes());
+    }
+    Ok((w, h, stride, output))
+}
+
+/// Decode the image into interleaved RGBA half-floats, with each channel normalized
+/// to the `0.0..=1.0` range. The output has `width * height * 4` values in it, and
+/// can be uploaded directly as an `Rgba16Float` texture.
+#[cfg(feature = "half")]
+pub fn decode_to_rgba16f<R>(input: R) -> Result<(usize, usize, Vec<half::f16>), Error>
+where
+    R: Read,
+{
+    // There are only 256 possible channel values, so convert each of them once
+    let table: [half::f16; 256] = core::array::from_fn(|v| half::f16::from_f32(v as f32 / 255.0));
+
+    let (w, h, pixels) = decode(input)?;
+    let mut output = Vec::with_capacity(initial_capacity(w * h) * 4);
+    for p in pixels {
+        let p = p?;
+        output.extend([p.r, p.g, p.b, p.a].map(|c| table[c as usize]));
+    }
+    Ok((w, h, output))
+}
+
+/// Decode as many pixels as possible from a damaged image, ignoring the size in its
+/// header. Opcodes are decoded until the end marker or the end of the stream is found,
+/// and all of the pixels decoded up to that point are returned. This can recover the
+/// pixels of an image whose width or height was corrupted, as long as its data is intact.
+///
+/// Streams that were cut short are decoded up to their last whole chunk. If the stream
+/// ends partway through the end marker, what's left of it is ignored, so a zero byte at
+/// the very end of the stream is taken for the start of the marker rather than a pixel.
+///
+/// Since the real size of the image is unknown, images stored in serpentine order (see
+/// [`EncodeOptions::reorder_for_runs`](crate::EncodeOptions::reorder_for_runs)) are
+/// returned in that order.
+#[inline]
+pub fn decode_until_eof<R>(input: R) -> Result<Vec<Pixel>, Error>
+where
+    R: Read,
+{
+    Decoder::new().decode_until_eof(input)
+}
+
+/// Decode the image's data block chunk by chunk instead of pixel by pixel, for seeing
+/// how an image was encoded. The header is read just like [`decode`] reads it, and the
+/// returned iterator yields each chunk along with its offset, in bytes from the start of
+/// the stream. Only images in the released QOI specification can be decoded this way.
+#[inline]
+pub fn decode_chunks<R>(input: R) -> Result<Chunks<R>, Error>
+where
+    R: Read,
+{
+    let (_, _, pixels) = decode(input)?;
+    Ok(Chunks { pixels })
+}
+
+/// Decode the image encoded in the bytes provided by `input`. The return value
+/// is the image's `width`, `height`, and an iterator to parse the actual pixel
+/// data. If you just want to read the image size, you can ignore the iterator.
+///
+/// The amount of pixels on a successful decode will always be `width * height`,
+/// so you can use those values to pre-allocate your pixel buffer if you want.
+/// If the data block would produce more pixels than that, decoding fails with
+/// [`Error::TooMuchData`], and if its end marker comes before the last pixel, it
+/// fails with [`Error::Truncated`]. A header with a channel count other than 3 or 4
+/// fails with [`Error::InvalidChannels`], and one with a colorspace other than 0 or 1
+/// fails with [`Error::InvalidColorspace`], unless it's decoded with [`decode_lenient`].
+///
+/// The pixel data is read through an internal buffer, so there's no need to wrap
+/// `input` in a `BufReader`.
+///
+/// Files in the pre-release draft format that older versions of this crate wrote share
+/// this format's header, but not its opcodes, so they have to be decoded with
+/// [`Decoder::legacy`]. If the data doesn't fit the image, and the stream turns out to end
+/// with the other format's end marker right there, the decoder assumes it was given a file
+/// in the other format and fails with [`Error::UnsupportedQoiVersion`]. Otherwise, bytes
+/// after the last pixel that aren't the end marker fail with [`Error::TooMuchData`], and a
+/// stream that ends partway through the end marker fails with [`Error::Truncated`].
+#[inline]
+pub fn decode<R>(input: R) -> Result<(usize, usize, Pixels<R>), Error>
+where
+    R: Read,
+{
+    Decoder::new().decode(input)
+}
+
+/// Decode the image like [`decode`], but accept a header with any channel count or
+/// colorspace, for nonstandard files that other decoders would reject. See
+/// [`Decoder::lenient`] for details.
+#[inline]
+pub fn decode_lenient<R>(input: R) -> Result<(usize, usize, Pixels<R>), Error>
+where
+    R: Read,
+{
+    Decoder::new().lenient(true).decode(input)
+}
+
+/// Decode the image encoded in `input` and re-encode it into the `output` stream, one
+/// pixel at a time, so the whole image is never held in memory. The new image has the
+/// same size, channels, and colorspace as the old one. Returns the size of the encoded
+/// data.
+///
+/// This normalizes files that decode to the right pixels but weren't written the usual
+/// way. To convert files in the pre-release draft format into the released QOI
+/// specification, transcode them with a [`Decoder::legacy`] decoder instead.
+#[inline]
+pub fn transcode<R, W>(input: R, output: W) -> Result<usize, Error>
+where
+    R: Read,
+    W: Write,
+{
+    Decoder::new().transcode(input, output)
+}
+
+/// Decode the image encoded in `bytes`, which holds the whole file in memory. The return
+/// value is the image's header, and an iterator to parse its pixels. This otherwise
+/// behaves just like [`decode`].
+#[inline]
+pub fn decode_slice(bytes: &[u8]) -> Result<(Header, Pixels<&[u8]>), Error> {
+    let (_, _, pixels) = decode(bytes)?;
+    Ok((pixels.header(), pixels))
+}
+
+/// Decode the image encoded in `bytes`, which holds the whole file in memory, filling
+/// `output` with its pixels. Returns the image's header.
+pub fn decode_slice_into_vec(bytes: &[u8], output: &mut Vec<Pixel>) -> Result<Header, Error> {
+    let (header, pixels) = decode_slice(bytes)?;
+    output.clear();
+    output.reserve(initial_capacity(header.width * header.height));
+    for p in pixels {
+        output.push(p?);
+    }
+    Ok(header)
+}
+
+/// A decoder that can be configured with options before decoding.
+///
+/// Calling [`decode`] is the same as decoding with a default `Decoder`.
+#[derive(Clone, Debug)]
+pub struct Decoder {
+    seed_lookup: Option<[Pixel; 64]>,
+    read_buffer: usize,
+    detect_opaque: bool,
+    strict_channels: bool,
+    legacy: bool,
+    flip_vertically: bool,
+    lenient: bool,
+}
+
+impl Default for Decoder {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            seed_lookup: None,
+            read_buffer: 8 * 1024,
+            detect_opaque: false,
+            strict_channels: false,
+            legacy: false,
+            flip_vertically: false,
+            lenient: false,
+        }
+    }
+}
+
+impl Decoder {
+    /// Create a decoder with the default options.
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the size, in bytes, of the buffer that the pixel data is read through.
+    /// Smaller buffers use less memory, but need more reads from the underlying
+    /// stream. The default is 8 KiB.
+    #[inline]
+    pub fn read_buffer(mut self, size: usize) -> Self {
+        self.read_buffer = size.max(1);
+        self
+    }
+
+    /// Pre-populate the running lookup table, for decoding images that were encoded
+    /// with [`EncodeOptions::seed_lookup`](crate::EncodeOptions::seed_lookup). The
+    /// table must be exactly the one that was used to encode the image.
+    #[inline]
+    pub fn seed_lookup(mut self, lookup: [Pixel; 64]) -> Self {
+        self.seed_lookup = Some(lookup);
+        self
+    }
+
+    /// Have [`decode_to_vec`](Self::decode_to_vec) report whether every pixel in the
+    /// image is fully opaque, which tells you if the image could be stored with 3
+    /// channels without a second pass over the pixels. This is always tracked while
+    /// decoding, and can also be checked with [`Pixels::all_opaque`].
+    #[inline]
+    pub fn detect_opaque(mut self, detect: bool) -> Self {
+        self.detect_opaque = detect;
+        self
+    }
+
+    /// Fail with [`Error::AlphaInRgbImage`] if an image that declares 3 channels in
+    /// its header contains pixels whose alpha isn't 255. A correct encoder never
+    /// produces these, so this catches encoder bugs and tampered files.
+    #[inline]
+    pub fn strict_channels(mut self, strict: bool) -> Self {
+        self.strict_channels = strict;
+        self
+    }
+
+    /// Decode images in the pre-release draft format, which older versions of this
+    /// crate wrote, instead of the released QOI specification. Both formats share the
+    /// same header, but use different opcodes, so the format can't be detected reliably.
+    #[inline]
+    pub fn legacy(mut self, legacy: bool) -> Self {
+        self.legacy = legacy;
+        self
+    }
+
+    /// Accept images whose header has a channel count other than 3 or 4, or a colorspace
+    /// other than 0 or 1, instead of failing with [`Error::InvalidChannels`] or
+    /// [`Error::InvalidColorspace`]. Since those values can't be represented, the
+    /// image's [`Header`] reports the defaults in their place.
+    #[inline]
+    pub fn lenient(mut self, lenient: bool) -> Self {
+        self.lenient = lenient;
+        self
+    }
+
+    /// Have [`decode_to_vec`](Self::decode_to_vec) return the rows of the image in
+    /// bottom-to-top order, like OpenGL textures expect. This doesn't affect the order
+    /// that [`decode`](Self::decode) yields pixels in.
+    #[inline]
+    pub fn flip_vertically(mut self, flip: bool) -> Self {
+        self.flip_vertically = flip;
+        self
+    }
+
+    /// Decode the whole image, returning its `width`, `height`, and pixels. If
+    /// [`detect_opaque`](Self::detect_opaque) is enabled, this also returns whether
+    /// every pixel had an alpha of 255, otherwise that value is `None`.
+    pub fn decode_to_vec<R>(
+        &self,
+        input: R,
+    ) -> Result<(usize, usize, Vec<Pixel>, Option<bool>), Error>
+    where
+        R: Read,
+    {
+        let (w, h, mut pixels) = self.decode(input)?;
+        let mut output = Vec::with_capacity(initial_capacity(w * h));
+        for p in &mut pixels {
+            output.push(p?);
+        }
+        if self.flip_vertically {
+            flip_rows(&mut output, w);
+        }
+        Ok((w, h, output, self.detect_opaque.then_some(pixels.opaque)))
+    }
+
+    /// Decode the image encoded in `input` using this decoder's options, and re-encode it
+    /// into the `output` stream in the released QOI specification. See [`transcode`] for
+    /// details.
+    pub fn transcode<R, W>(&self, input: R, output: W) -> Result<usize, Error>
+    where
+        R: Read,
+        W: Write,
+    {
+        let (w, h, pixels) = self.decode(input)?;
+        let header = pixels.header();
+        let width = NonZeroUsize::new(w).ok_or(Error::NoImageSize)?;
+        let height = NonZeroUsize::new(h).ok_or(Error::NoImageSize)?;
+        let mut encoder =
+            Encoder::with_colorspace(width, height, header.channels, header.colorspace, output)?;
+        for p in pixels {
+            encoder.push(p?)?;
+        }
+        encoder.finish()
+    }
+
+    /// Decode as many pixels as possible from a damaged image, using this decoder's
+    /// options. See [`decode_until_eof`] for details.
+    pub fn decode_until_eof<R>(&self, mut input: R) -> Result<Vec<Pixel>, Error>
+    where
+        R: Read,
+    {
+        // Only the file type marker has to be intact
+        let magic = read_u32(&mut input, 0)?;
+        if magic != MAGIC {
+            return Err(Error::InvalidFileTypeMarker(magic.to_be_bytes()));
+        }
+        let [_, _, _, _, _, _, _, _, channels, _] = read::<R, 10>(&mut input, 4)?;
+
+        // Read the whole data block, so we can look ahead for the end marker
+        let mut data = Vec::new();
+        input.read_to_end(&mut data)?;
+        let channels = Channels::try_from(channels).unwrap_or_default();
+        let mut pixels = self.pixels(&data[..], 1, usize::MAX, channels, 0);
+        let marker: &[u8] = if self.legacy {
+            &LEGACY_END_MARKER
+        } else {
+            &END_MARKER
+        };
+
+        let mut output = Vec::new();
+        loop {
+            // The encoder never writes the same index twice in a row (it writes a run
+            // instead), so the end marker can't be mistaken for pixel data. If the stream
+            // was cut short partway through the marker, only its start is left.
+            let rest = &data[pixels.offset() - HEADER_SIZE..];
+            if pixels.run == 0 && (rest.starts_with(marker) || marker.starts_with(rest)) {
+                break;
+            }
+
+            // A stream cut short partway through a chunk ends with the chunk before it
+            match pixels.parse() {
+                Ok(px) => output.push(px),
+                Err(
+                    Error::UnexpectedEof { .. }
+                    | Error::Truncated { .. }
+                    | Error::UnsupportedQoiVersion,
+                ) => break,
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(output)
+    }
+
+    /// Decode the image encoded in the bytes provided by `input`, using this decoder's
+    /// options. See [`decode`] for details on the return value.
+    pub fn decode<R>(&self, mut input: R) -> Result<(usize, usize, Pixels<R>), Error>
+    where
+        R: Read,
+    {
+        let (header, color_space) = read_header(&mut input, self.lenient)?;
+        let pixels = self.pixels(
+            input,
+            header.width,
+            header.height,
+            header.channels,
+            color_space,
+        );
+        Ok((header.width, header.height, pixels))
+    }
+
+    /// Create an iterator to decode the pixels of an image with the provided header.
+    fn pixels<R>(
+        &self,
+        input: R,
+        width: usize,
+        height: usize,
+        channels: Channels,
+        color_space: u8,
+    ) -> Pixels<R> {
+        Pixels {
+            input,
+            buf: vec![0; self.read_buffer],
+            pos: 0,
+            len: 0,
+            filled: 0,
+            tail: [0; 8],
+            remaining: width * height,
+            px: Pixel::rgba(0, 0, 0, 255),
+            run: 0,
+            repeat: false,
+            lookup: self.seed_lookup.unwrap_or([Pixel::transparent(); 64]),
+            legacy: self.legacy,
+            width,
+            height,
+            channels,
+            color_space: Colorspace::try_from(color_space & !SERPENTINE_FLAG).unwrap_or_default(),
+            serpentine: (color_space & SERPENTINE_FLAG) != 0,
+            row: Vec::new(),
+            opaque: true,
+            strict_rgb: self.strict_channels && channels == Channels::Rgb,
+            end_marker: None,
+            failed: None,
+        }
+    }
+}
+
+/// An iterator that parses pixels from the encoded image's data block.
+///
+/// Since this iterator parses the data as it goes, it iterates over
+/// `Result` values that will carry an error if the parser fails. It
+/// yields at most as many items as there are pixels left in the image,
+/// but it ends early if an error is returned, so its exact length can't
+/// be known ahead of time.
+///
+/// If the reader can be cloned, so can this, which makes a copy of the
+/// decoder that carries on from the same point independently.
+#[derive(Clone)]
+pub struct Pixels<R> {
+    input: R,
+    buf: Vec<u8>,
+    pos: usize,
+    len: usize,
+    filled: usize,
+    tail: [u8; 8],
+    remaining: usize,
+    px: Pixel,
+    run: u16,
+    repeat: bool,
+    lookup: [Pixel; 64],
+    legacy: bool,
+    width: usize,
+    height: usize,
+    channels: Channels,
+    color_space: Colorspace,
+    serpentine: bool,
+    row: Vec<Pixel>,
+    opaque: bool,
+    strict_rgb: bool,
+    end_marker: Option<bool>,
+    failed: Option<usize>,
+}
+
+impl<R> Pixels<R>
+where
+    R: Read,
+{
+    /// Iterate over only the successfully parsed pixels. This iterator
+    /// will panic if the parser encounters an error.
+    #[inline]
+    pub fn unwrapped(&mut self) -> Unwrapped<'_, Self> {
+        Unwrapped {
+            width: self.width,
+            pixels: self,
+        }
+    }
+
+    /// Iterate over only the successfully parsed pixels. This iterator
+    /// will silently end if the parser encounters an error.
+    #[inline]
+    pub fn ok(&mut self) -> Okay<'_, Self> {
+        Okay {
+            width: self.width,
+            pixels: self,
+        }
+    }
+
+    /// Iterate over the pixels while also supplying the position of
+    /// each pixel in the resulting image.
+    #[inline]
+    pub fn positioned(&mut self) -> Positioned<'_, Self> {
+        Positioned::new(self, self.width)
+    }
+
+    /// Iterate over the pixels along with a flag that is `true` if the pixel was
+    /// produced by a run of the previous pixel, rather than decoded from its own
+    /// opcode. This is useful for visualizing how well an image compressed.
+    #[inline]
+    pub fn annotated(&mut self) -> Annotated<'_, R> {
+        Annotated { pixels: self }
+    }
+
+    /// Get the information stored in the image's header.
+    #[inline]
+    pub fn header(&self) -> Header {
+        Header {
+            width: self.width,
+            height: self.height,
+            channels: self.channels,
+            colorspace: self.color_space,
+        }
+    }
+
+    /// The amount of pixels left to be decoded, if no errors end decoding first. Once an
+    /// error has ended the iterator, there are none left.
+    #[inline]
+    pub fn remaining(&self) -> usize {
+        // Pixels from a reversed row have already been parsed, but not handed out yet
+        self.remaining + self.row.len()
+    }
+
+    /// The amount of pixels that have been decoded so far. Once an error has ended the
+    /// iterator, this is how many were decoded before it, including any that never got
+    /// handed out because of it.
+    #[inline]
+    pub fn pixels_decoded(&self) -> usize {
+        self.failed
+            .unwrap_or_else(|| self.width * self.height - self.remaining())
+    }
+
+    /// Whether every pixel in the image had an alpha of 255, meaning it could be stored
+    /// with 3 channels without losing anything. This is `None` until every pixel has
+    /// been decoded and what follows them has been checked, and stays `None` if an error
+    /// ended decoding, even if it came after the last pixel.
+    #[inline]
+    pub fn all_opaque(&self) -> Option<bool> {
+        (self.end_marker.is_some() && self.failed.is_none()).then_some(self.opaque)
+    }
+
+    /// Turn this into an iterator that parses one row of pixels at a time, allocating
+    /// a new `Vec` for each row.
+    #[inline]
+    pub fn into_row_iter(self) -> RowIter<R> {
+        RowIter { pixels: self }
+    }
+
+    /// Parse the next row of pixels into `output`, replacing whatever was in it, so the
+    /// same buffer can be reused for every row. Returns `None` once every row has been
+    /// parsed. If an error is returned, `output` holds the pixels parsed before it.
+    pub fn next_row(&mut self, output: &mut Vec<Pixel>) -> Option<Result<(), Error>> {
+        output.clear();
+        if self.remaining() == 0 {
+            return None;
+        }
+        let width = self.width;
+        for p in self.by_ref().take(width) {
+            match p {
+                Ok(p) => output.push(p),
+                Err(err) => return Some(Err(err)),
+            }
+        }
+        Some(Ok(()))
+    }
+
+    /// Get a reference to the reader the pixels are being parsed from.
+    #[inline]
+    pub fn get_ref(&self) -> &R {
+        &self.input
+    }
+
+    /// Get a mutable reference to the reader the pixels are being parsed from.
+    ///
+    /// Since the reader is buffered internally, it will usually be ahead of the
+    /// decoder's position in the stream.
+    #[inline]
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.input
+    }
+
+    /// Take a snapshot of the decoder's state, so that decoding can be paused here and
+    /// resumed later with [`restore_state`](Self::restore_state).
+    ///
+    /// The snapshot doesn't include the reader, so if you want to resume decoding with a
+    /// different reader (or a reader that has moved since), you'll need to put it back
+    /// at the snapshot's [`offset`](DecodeState::offset) before restoring it.
+    ///
+    /// For images stored in serpentine order (see
+    /// [`EncodeOptions::reorder_for_runs`](crate::EncodeOptions::reorder_for_runs)),
+    /// snapshots should be taken at the start of a row.
+    #[inline]
+    pub fn save_state(&self) -> DecodeState {
+        DecodeState {
+            width: self.width,
+            height: self.height,
+            channels: self.channels,
+            legacy: self.legacy,
+            serpentine: self.serpentine,
+            offset: self.offset(),
+            px: self.px,
+            lookup: self.lookup,
+            run: self.run,
+            remaining: self.remaining,
+            opaque: self.opaque,
+        }
+    }
+
+    /// Restore the decoder's state from a snapshot taken with [`save_state`](Self::save_state).
+    /// Decoding will continue from where the snapshot was taken, reading the rest of the
+    /// image's data from the current position of the reader.
+    ///
+    /// The snapshot has to come from a decoder for an image with the same header, or this
+    /// fails with [`Error::DimensionMismatch`] if its size is different, or
+    /// [`Error::StateMismatch`] if it was stored differently. The decoder is left as it
+    /// was if it fails.
+    pub fn restore_state(&mut self, state: DecodeState) -> Result<(), Error> {
+        if (state.width, state.height) != (self.width, self.height) {
+            return Err(Error::DimensionMismatch {
+                expected: (self.width, self.height),
+                found: (state.width, state.height),
+            });
+        }
+        if state.channels != self.channels
+            || state.legacy != self.legacy
+            || state.serpentine != self.serpentine
+        {
+            return Err(Error::StateMismatch);
+        }
+
+        self.pos = 0;
+        self.len = 0;
+        self.filled = state.offset - HEADER_SIZE;
+        self.tail = [0; 8];
+        self.px = state.px;
+        self.lookup = state.lookup;
+        self.run = state.run;
+        self.remaining = state.remaining;
+        self.opaque = state.opaque;
+        self.row.clear();
+        self.end_marker = None;
+        self.failed = None;
+        Ok(())
+    }
+
+    /// Make sure the image's data block was followed by its end marker. Streams that end
+    /// right after the last pixel are usually accepted, but this fails with
+    /// [`Error::MissingEndMarker`] for them, or if an error ended decoding early. Any
+    /// pixels that haven't been decoded yet are decoded and discarded first.
+    pub fn verify_end_marker(&mut self) -> Result<(), Error> {
+        for p in self.by_ref() {
+            p?;
+        }
+        match self.end_marker {
+            Some(true) => Ok(()),
+            _ => Err(Error::MissingEndMarker),
+        }
+    }
+
+    /// Skip the rest of the current run, returning how many more times it repeats
+    /// the last pixel.
+    fn take_run(&mut self) -> Result<usize, Error> {
+        let n = (self.run as usize).min(self.remaining);
+        self.run -= n as u16;
+        self.remaining -= n;
+        if n > 0 && self.remaining == 0 {
+            self.check_end_marker()?;
+        }
+        Ok(n)
+    }
+
+    /// Get a reader over the rest of the stream, starting with any bytes that were
+    /// buffered but not decoded.
+    #[cfg(feature = "std")]
+    pub(crate) fn into_rest(self) -> std::io::Chain<std::io::Cursor<Vec<u8>>, R> {
+        let mut buf = self.buf;
+        buf.truncate(self.len);
+        let mut rest = std::io::Cursor::new(buf);
+        rest.set_position(self.pos as u64);
+        rest.chain(self.input)
+    }
+
+    /// The most bytes that decoding the next pixel could read from the stream, past what's
+    /// already buffered, including the end marker after the last pixel.
+    #[cfg(feature = "tokio")]
+    pub(crate) fn bytes_needed(&self) -> usize {
+        // A row stored right-to-left is decoded all at once when we get to it
+        let w = self.width * self.height;
+        let reversed_row =
+            self.serpentine && self.row.is_empty() && (w - self.remaining) / self.width % 2 == 1;
+        let chunks = if reversed_row { self.width } else { 1 };
+        (chunks * 5 + END_MARKER.len()).saturating_sub(self.len - self.pos)
+    }
+
+    /// The amount of bytes of the stream that have been decoded, including the header.
+    #[inline]
+    fn offset(&self) -> usize {
+        HEADER_SIZE + self.filled - (self.len - self.pos)
+    }
+
+    /// Refill the read buffer from the input.
+    #[cold]
+    fn fill(&mut self) -> Result<(), Error> {
+        // Remember the last few bytes we've read, so we can tell what the stream ended with
+        self.drop_buffered(self.len);
+
+        loop {
+            match self.input.read(&mut self.buf) {
+                Ok(0) if self.remaining > 0 && self.ends_in_other_format() => {
+                    return Err(Error::UnsupportedQoiVersion)
+                }
+                Ok(0) if self.remaining > 0 && self.ends_in_end_marker() => {
+                    // Every byte of the marker was decoded as a 1-byte chunk
+                    return Err(self.truncated(self.end_marker().len()));
+                }
+                Ok(0) => {
+                    return Err(Error::UnexpectedEof {
+                        offset: self.offset(),
+                    })
+                }
+                Ok(n) => {
+                    self.pos = 0;
+                    self.len = n;
+                    self.filled += n;
+                    return Ok(());
+                }
+                Err(err) if err.kind() == ErrorKind::Interrupted => {}
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+
+    #[inline]
+    fn read_u8(&mut self) -> Result<u8, Error> {
+        if self.pos == self.len {
+            self.fill()?;
+        }
+        let b = self.buf[self.pos];
+        self.pos += 1;
+        Ok(b)
+    }
+
+    #[inline]
+    fn read<const N: usize>(&mut self) -> Result<[u8; N], Error> {
+        let mut bytes = [0; N];
+        for b in &mut bytes {
+            *b = self.read_u8()?;
+        }
+        Ok(bytes)
+    }
+
+    /// Get the next pixel in the order it's stored in the data block.
+    #[inline]
+    fn next_stored(&mut self) -> Option<Result<Pixel, Error>> {
+        (self.remaining > 0).then(|| {
+            let mut result = self.parse();
+
+            // Make sure the image actually ended where we expected it to
+            if result.is_ok() && self.remaining == 0 {
+                if let Err(err) = self.check_end_marker() {
+                    result = Err(err);
+                }
+            }
+
+            // If we get an error while parsing, end the iterator
+            result.map_err(|err| self.fail(err))
+        })
+    }
+
+    /// Get the next pixel of an image stored in serpentine order, where every odd row
+    /// is stored right-to-left.
+    #[cold]
+    fn next_serpentine(&mut self) -> Option<Result<Pixel, Error>> {
+        if let Some(px) = self.row.pop() {
+            return Some(Ok(px));
+        }
+
+        // Even rows are stored as usual
+        let y = (self.width * self.height - self.remaining) / self.width;
+        if y.is_multiple_of(2) {
+            return self.next_stored();
+        }
+
+        // Parse the whole of an odd row so we can hand it out in reverse
+        let mut row = core::mem::take(&mut self.row);
+        for _ in 0..self.width {
+            match self.next_stored()? {
+                Ok(px) => row.push(px),
+                Err(err) => return Some(Err(err)),
+            }
+        }
+        self.row = row;
+        self.row.pop().map(Ok)
+    }
+
+    /// Drop the first `n` bytes of the read buffer, moving the rest to its start. The last
+    /// few of the dropped bytes are kept in the tail, to tell what the stream ended with.
+    fn drop_buffered(&mut self, n: usize) {
+        let kept = n.min(8);
+        self.tail.rotate_left(kept);
+        self.tail[8 - kept..].copy_from_slice(&self.buf[n - kept..n]);
+        self.buf.copy_within(n..self.len, 0);
+        self.pos = self.pos.saturating_sub(n);
+        self.len -= n;
+    }
+
+    /// The last 8 bytes that have been read from the stream, including any buffered ones.
+    fn last_bytes(&self) -> [u8; 8] {
+        let mut bytes = self.tail;
+        let n = self.len.min(8);
+        bytes.rotate_left(n);
+        bytes[8 - n..].copy_from_slice(&self.buf[self.len - n..self.len]);
+        bytes
+    }
+
+    /// Check if the last bytes read from the stream are the end marker of the format we're
+    /// not decoding, which means we've been decoding the wrong format.
+    fn ends_in_other_format(&self) -> bool {
+        // The tail starts out zeroed, so it only means anything once it's been filled
+        let last = self.last_bytes();
+        if self.legacy {
+            self.filled >= END_MARKER.len() && last == END_MARKER
+        } else {
+            self.filled >= LEGACY_END_MARKER.len() && last[4..] == LEGACY_END_MARKER
+        }
+    }
+
+    /// Handle data that doesn't fit the image's size, which usually means it's in the
+    /// other format. `err` is returned unless the stream is known to end with the other
+    /// format's end marker right after what's been buffered.
+    ///
+    /// To find that out, the stream is read from at most once more, into whatever room is
+    /// left in the buffer. Skipping the whole rest of the stream would never finish for
+    /// endless streams, and would consume any images that come after this one.
+    #[cold]
+    fn invalid_data(&mut self, err: Error) -> Error {
+        // Make room after the bytes that haven't been decoded yet
+        self.drop_buffered(self.pos);
+        if self.len == self.buf.len() {
+            return err;
+        }
+        loop {
+            match self.input.read(&mut self.buf[self.len..]) {
+                Ok(0) if self.ends_in_other_format() => return Error::UnsupportedQoiVersion,
+                Ok(n) => {
+                    self.len += n;
+                    self.filled += n;
+                    return err;
+                }
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(_) => return err,
+            }
+        }
+    }
+
+    /// Check if the last bytes read from the stream are the end marker of the format
+    /// we're decoding.
+    fn ends_in_end_marker(&self) -> bool {
+        let marker = self.end_marker();
+        self.filled >= marker.len() && self.last_bytes().ends_with(marker)
+    }
+
+    /// The end marker for the format we're decoding.
+    fn end_marker(&self) -> &'static [u8] {
+        if self.legacy {
+            &LEGACY_END_MARKER
+        } else {
+            &END_MARKER
+        }
+    }
+
+    /// Check if the rest of the end marker comes right after its first byte, which
+    /// was just read. It's only looked for in what's already been buffered.
+    fn end_marker_follows(&self) -> bool {
+        self.buf[self.pos..self.len].starts_with(&self.end_marker()[1..])
+    }
+
+    /// The error for a stream whose end marker came before the last pixel, where
+    /// `marker_pixels` of the pixels decoded so far were really the marker's bytes.
+    fn truncated(&self, marker_pixels: usize) -> Error {
+        let expected = self.width * self.height;
+        Error::Truncated {
+            expected,
+            got: (expected - self.remaining).saturating_sub(marker_pixels),
+        }
+    }
+
+    /// End decoding because of `err`, which is returned.
+    #[cold]
+    fn fail(&mut self, err: Error) -> Error {
+        let decoded = self.width * self.height - self.remaining;
+        self.failed.get_or_insert(decoded);
+        self.remaining = 0;
+        err
+    }
+
+    /// Check that the end marker follows the last pixel, ending decoding if it doesn't.
+    fn check_end_marker(&mut self) -> Result<(), Error> {
+        self.read_end_marker().map_err(|err| self.fail(err))
+    }
+
+    /// Read what follows the last pixel. Streams that end right after the last pixel are
+    /// accepted, but one that ends partway through the end marker is truncated, and any
+    /// other bytes mean there's more data than fits in the image.
+    fn read_end_marker(&mut self) -> Result<(), Error> {
+        self.end_marker = Some(false);
+        for (i, &expected) in self.end_marker().iter().enumerate() {
+            match self.read_u8() {
+                Ok(b) if b == expected => {}
+                Ok(_) => return Err(self.invalid_data(Error::TooMuchData)),
+                Err(Error::UnexpectedEof { .. }) if i == 0 => return Ok(()),
+                Err(Error::UnexpectedEof { .. }) => return Err(self.truncated(0)),
+                Err(err) => return Err(err),
+            }
+        }
+        self.end_marker = Some(true);
+        Ok(())
+    }
+
+    fn parse(&mut self) -> Result<Pixel, Error> {
+        // If we've got a run, just count it down and return the same pixel again
+        if self.run > 0 {
+            self.run -= 1;
+            self.repeat = true;
+        } else {
+            // Read the first byte, which will contain the tag
+            let b1 = self.read_u8()?;
+
+            // Both formats' end markers start with a byte that's never repeated in a
+            // valid data block, so if it's followed by the rest of one before the last
+            // pixel, we've hit the end of the image early
+            if b1 == 0 && self.remaining > 1 && self.end_marker_follows() {
+                let err = self.truncated(0);
+                return Err(self.invalid_data(err));
+            }
+
+            if self.legacy {
+                self.parse_legacy(b1)?;
+            } else {
+                self.parse_chunk(b1)?;
+            }
+
+            // Don't let a run carry on past the end of the image. This also makes sure
+            // there's a pixel left for this chunk, and runs only ever count down along
+            // with the pixels remaining, so counting off a pixel below can't underflow.
+            if self.run as usize >= self.remaining {
+                return Err(self.invalid_data(Error::TooMuchData));
+            }
+
+            // Runs repeat the previous pixel, so we only need to check alpha here
+            self.opaque &= self.px.a == 255;
+            if self.strict_rgb && self.px.a != 255 {
+                return Err(Error::AlphaInRgbImage);
+            }
+        }
+
+        self.remaining -= 1;
+        Ok(self.px)
+    }
+
+    /// Parse a chunk of the released QOI specification, starting with the tag `b1`.
+    #[inline]
+    fn parse_chunk(&mut self, b1: u8) -> Result<(), Error> {
+        self.repeat = false;
+        if b1 == OP_RGB {
+            // The pixel is stored as its RGB values, keeping the previous alpha
+            let [r, g, b] = self.read::<3>()?;
+            self.px = Pixel::rgba(r, g, b, self.px.a);
+        } else if b1 == OP_RGBA {
+            // The pixel is stored as its RGBA values
+            let [r, g, b, a] = self.read::<4>()?;
+            self.px = Pixel::rgba(r, g, b, a);
+        } else if (b1 & MASK_2) == OP_INDEX {
+            // If the pixel is indexed, get the value from the lookup table
+            self.px = self.lookup[b1 as usize];
+        } else if (b1 & MASK_2) == OP_DIFF {
+            // Each channel's difference from the previous pixel is stored in 2 bits
+            self.px.r = self.px.r.wrapping_add(((b1 >> 4) & 0x03).wrapping_sub(2));
+            self.px.g = self.px.g.wrapping_add(((b1 >> 2) & 0x03).wrapping_sub(2));
+            self.px.b = self.px.b.wrapping_add((b1 & 0x03).wrapping_sub(2));
+        } else if (b1 & MASK_2) == OP_LUMA {
+            // The green difference is stored in 6 bits, and the red and blue differences
+            // are stored in 4 bits each, relative to the green difference
+            let b2 = self.read_u8()?;
+            let vg = (b1 & 0x3f).wrapping_sub(32);
+            self.px.r = self
+                .px
+                .r
+                .wrapping_add(vg.wrapping_sub(8).wrapping_add(b2 >> 4));
+            self.px.g = self.px.g.wrapping_add(vg);
+            self.px.b = self
+                .px
+                .b
+                .wrapping_add(vg.wrapping_sub(8).wrapping_add(b2 & 0x0f));
+        } else if (b1 & MASK_2) == OP_RUN {
+            // The pixel is the start of a run, so get the run length
+            self.run = (b1 & 0x3f) as u16;
+            self.repeat = true;
+        }
+
+        // Put the new pixel into the lookup table
+        self.lookup[self.px.index() as usize] = self.px;
+        Ok(())
+    }
+
+    /// Parse a chunk of the pre-release draft format, starting with the tag `b1`.
+    #[inline]
+    fn parse_legacy(&mut self, b1: u8) -> Result<(), Error> {
+        // Both run opcodes share their top 2 bits
+        self.repeat = (b1 & MASK_2) == RUN_8;
+
+        if (b1 & MASK_2) == INDEX {
+            // If the pixel is indexed, get the value from the lookup table
+            self.px = self.lookup[(b1 ^ INDEX) as usize];
+        } else if (b1 & MASK_3) == RUN_8 {
+            // If the pixel is a short run, get the run length
+            self.run = (b1 & 0x1f) as u16;
+        } else if (b1 & MASK_3) == RUN_16 {
+            // If the pixel is a long run, get the run length
+            let b2 = self.read_u8()?;
+            self.run = ((((b1 & 0x1f) as u16) << 8) | (b2 as u16)) + 32;
+        } else if (b1 & MASK_2) == DIFF_8 {
+            self.px.r = self.px.r.wrapping_add(((b1 >> 4) & 0x03).wrapping_sub(2));
+            self.px.g = self.px.g.wrapping_add(((b1 >> 2) & 0x03).wrapping_sub(2));
+            self.px.b = self.px.b.wrapping_add((b1 & 0x03).wrapping_sub(2));
+        } else if (b1 & MASK_3) == DIFF_16 {
+            let b2 = self.read_u8()?;
+            self.px.r = self.px.r.wrapping_add((b1 & 0x1f).wrapping_sub(16));
+            self.px.g = self.px.g.wrapping_add((b2 >> 4).wrapping_sub(8));
+            self.px.b = self.px.b.wrapping_add((b2 & 0x0f).wrapping_sub(8));
+        } else if (b1 & MASK_4) == DIFF_24 {
+            let [b2, b3] = self.read::<2>()?;
+            self.px.r = self
+                .px
+                .r
+                .wrapping_add((((b1 & 0x0f) << 1) | (b2 >> 7)).wrapping_sub(16));
+            self.px.g = self.px.g.wrapping_add(((b2 & 0x7c) >> 2).wrapping_sub(16));
+            self.px.b = self
+                .px
+                .b
+                .wrapping_add((((b2 & 0x03) << 3) | ((b3 & 0xe0) >> 5)).wrapping_sub(16));
+            self.px.a = self.px.a.wrapping_add((b3 & 0x1f).wrapping_sub(16));
+        } else if (b1 & MASK_4) == COLOR {
+            if (b1 & 8) != 0 {
+                self.px.r = self.read_u8()?;
+            }
+            if (b1 & 4) != 0 {
+                self.px.g = self.read_u8()?;
+            }
+            if (b1 & 2) != 0 {
+                self.px.b = self.read_u8()?;
+            }
+            if (b1 & 1) != 0 {
+                self.px.a = self.read_u8()?;
+            }
+        }
+
+        // Put the new pixel into the lookup table
+        self.lookup[(self.px.legacy_hash() % 64) as usize] = self.px;
+        Ok(())
+    }
+}
+
+impl<R> Iterator for Pixels<R>
+where
+    R: Read,
+{
+    type Item = Result<Pixel, Error>;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.serpentine {
+            self.next_serpentine()
+        } else {
+            self.next_stored()
+        }
+    }
+
+    fn nth(&mut self, mut n: usize) -> Option<Self::Item> {
+        // Reversed rows have to be buffered, so skip those pixel by pixel
+        if self.serpentine {
+            for _ in 0..n {
+                if let Err(err) = self.next()? {
+                    return Some(Err(err));
+                }
+            }
+            return self.next();
+        }
+
+        // Skip ahead, consuming runs all at once instead of pixel by pixel
+        while n > 0 && self.remaining > 0 {
+            if self.run > 0 {
+                let skip = n.min(self.run as usize).min(self.remaining);
+                self.run -= skip as u16;
+                self.remaining -= skip;
+                n -= skip;
+            } else if let Err(err) = self.parse() {
+                return Some(Err(self.fail(err)));
+            } else {
+                n -= 1;
+            }
+
+            // Make sure the image actually ended where we expected it to
+            if self.remaining == 0 {
+                if let Err(err) = self.check_end_marker() {
+                    return Some(Err(err));
+                }
+            }
+        }
+        self.next()
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        // Any pixels from a reversed row are sure to be handed out, and if there are more
+        // to be decoded, there's at least either one of them or an error
+        let sure = self.row.len() + self.remaining.min(1);
+        (sure, Some(self.remaining()))
+    }
 }
 
-#[inline]
-fn read_u32<R: Read>(input: &mut R) -> Result<u32, Error> {
-    Ok(u32::from_be_bytes(read::<R, 4>(input)?))
+// Errors end the iterator by leaving no pixels remaining, so it never resumes
+impl<R> FusedIterator for Pixels<R> where R: Read {}
+
+/// An iterator that parses the image's data block one row at a time. Only one
+/// row is held in memory at once, so rows can be processed and dropped as the
+/// image is decoded.
+///
+/// If the parser encounters an error, this iterator will return it and finish.
+#[derive(Clone)]
+pub struct RowIter<R> {
+    pixels: Pixels<R>,
 }
 
-/// Decode the image, filling `output` with the image's pixels.
-#[inline]
-pub fn decode_into_vec<R>(input: R, output: &mut Vec<Pixel>) -> Result<(usize, usize), Error>
+impl<R> Iterator for RowIter<R>
 where
     R: Read,
 {
-    let (w, h, pixels) = decode(input)?;
-    output.clear();
-    output.reserve(w * h);
-    for p in pixels {
-        output.push(p?);
+    type Item = Result<Vec<Pixel>, Error>;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        (self.pixels.remaining > 0).then(|| {
+            let width = self.pixels.width;
+            let mut row = Vec::with_capacity(width);
+            for p in self.pixels.by_ref().take(width) {
+                row.push(p?);
+            }
+            Ok(row)
+        })
     }
-    Ok((w, h))
 }
 
-/// Decode the image file.
-#[inline]
-pub fn decode_file<F>(path: F) -> Result<(usize, usize, Pixels<BufReader<File>>), Error>
-where
-    F: AsRef<Path>,
-{
-    decode(BufReader::new(File::open(path)?))
+impl<R> FusedIterator for RowIter<R> where R: Read {}
+
+/// A snapshot of the state of a [`Pixels`] decoder, for pausing and resuming decoding.
+#[derive(Clone, Debug)]
+pub struct DecodeState {
+    width: usize,
+    height: usize,
+    channels: Channels,
+    legacy: bool,
+    serpentine: bool,
+    offset: usize,
+    px: Pixel,
+    lookup: [Pixel; 64],
+    run: u16,
+    remaining: usize,
+    opaque: bool,
 }
 
-/// Decode the image file, filling `output` with the image's pixels.
-#[inline]
-pub fn decode_file_into_vec<F>(path: F, output: &mut Vec<Pixel>) -> Result<(usize, usize), Error>
-where
-    F: AsRef<Path>,
-{
-    decode_into_vec(BufReader::new(File::open(path)?), output)
+impl DecodeState {
+    /// The position in the stream, in bytes from the start of the image's header,
+    /// that the snapshot was taken at.
+    #[inline]
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// The amount of pixels that were left to be decoded when the snapshot was taken.
+    #[inline]
+    pub fn remaining(&self) -> usize {
+        self.remaining
+    }
 }
 
-/// Decode the image encoded in the bytes provided by `input`. The return value
-/// is the image's `width`, `height`, and an iterator to parse the actual pixel
-/// data. If you just want to read the image size, you can ignore the iterator.
-///
-/// The amount of pixels on a successful decode will always be `width * height`,
-/// so you can use those values to pre-allocate your pixel buffer if you want.
-pub fn decode<R>(mut input: R) -> Result<(usize, usize, Pixels<R>), Error>
+/// An iterator that parses pixels from the encoded image's data block, along
+/// with whether each pixel was repeated by a run. See [`Pixels::annotated`].
+pub struct Annotated<'a, R> {
+    pixels: &'a mut Pixels<R>,
+}
+
+impl<'a, R> Iterator for Annotated<'a, R>
 where
     R: Read,
 {
-    // Parse the magic filetype marker.
-    let magic = read_u32(&mut input)?;
-    if magic != MAGIC {
-        return Err(Error::InvalidFileTypeMarker(magic.to_be_bytes()));
+    type Item = Result<(Pixel, bool), Error>;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.pixels
+            .next()
+            .map(|p| p.map(|p| (p, self.pixels.repeat)))
     }
 
-    // Parse the image size
-    let width = read_u32(&mut input)? as usize;
-    let height = read_u32(&mut input)? as usize;
-    let _channels = read_u8(&mut input)?;
-    let _color_space = read_u8(&mut input)?;
-    if width == 0 || height == 0 {
-        return Err(Error::NoImageSize);
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.pixels.size_hint()
     }
+}
 
-    // Return the image info and an iterator to decode the pixels
-    Ok((
-        width,
-        height,
-        Pixels {
-            input,
-            remaining: width * height,
-            px: Pixel::rgba(0, 0, 0, 255),
-            run: 0,
-            lookup: [Pixel::transparent(); 64],
-            width,
-        },
-    ))
+impl<'a, R> FusedIterator for Annotated<'a, R> where R: Read {}
+
+/// A chunk of an encoded image's data block, as yielded by [`Chunks`].
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum Op {
+    /// The pixel is the one at this position in the running lookup table.
+    Index(u8),
+
+    /// Each channel of the pixel differs from the previous pixel's by this much,
+    /// from -2 to 1.
+    Diff { dr: i8, dg: i8, db: i8 },
+
+    /// The pixel's green channel differs from the previous pixel's by `dg`, from -32
+    /// to 31, and its red and blue channels differ by `dr_dg` and `db_dg` more than
+    /// that, from -8 to 7.
+    Luma { dg: i8, dr_dg: i8, db_dg: i8 },
+
+    /// The previous pixel is repeated this many times, from 1 to 62.
+    Run(u16),
+
+    /// The pixel is stored as its RGB values, keeping the previous pixel's alpha.
+    Rgb(Pixel),
+
+    /// The pixel is stored as its RGBA values.
+    Rgba(Pixel),
 }
 
-/// An iterator that parses pixels from the encoded image's data block.
+/// An iterator that parses the chunks of the encoded image's data block, along
+/// with the offset of each one in the stream. See [`decode_chunks`].
 ///
-/// Since this iterator parses the data as it goes, it iterates over
-/// `Result` values that will carry an error if the parser fails.
-pub struct Pixels<R> {
-    input: R,
-    remaining: usize,
-    px: Pixel,
-    run: u16,
-    lookup: [Pixel; 64],
-    width: usize,
+/// Chunks are yielded in the order they're stored in, so in images stored in
+/// serpentine order, odd rows are right-to-left. If the parser encounters an
+/// error, this iterator will return it and finish.
+pub struct Chunks<R> {
+    pixels: Pixels<R>,
 }
 
-impl<R> Pixels<R>
+impl<R> Chunks<R>
 where
     R: Read,
 {
-    /// Iterate over only the successfully parsed pixels. This iterator
-    /// will panic if the parser encounters an error.
+    /// Get the information stored in the image's header.
     #[inline]
-    pub fn unwrapped(&mut self) -> Unwrapped<'_, Self> {
-        Unwrapped {
-            width: self.width,
-            pixels: self,
-        }
+    pub fn header(&self) -> Header {
+        self.pixels.header()
     }
 
-    /// Iterate over only the successfully parsed pixels. This iterator
-    /// will silently end if the parser encounters an error.
-    #[inline]
-    pub fn ok(&mut self) -> Okay<'_, Self> {
-        Okay {
-            width: self.width,
-            pixels: self,
-        }
-    }
+    fn parse(&mut self) -> Result<(usize, Op), Error> {
+        let pixels = &mut self.pixels;
+        let offset = pixels.offset();
+        let prev = pixels.px;
 
-    /// Iterate over the pixels while also supplying the position of
-    /// each pixel in the resulting image.
-    #[inline]
-    pub fn positioned(&mut self) -> Positioned<'_, Self> {
-        Positioned::new(self, self.width)
-    }
+        // Decode the chunk just like a pixel would be, checking for an early end marker
+        let b1 = pixels.read_u8()?;
+        if b1 == 0 && pixels.remaining > 1 && pixels.end_marker_follows() {
+            let err = pixels.truncated(0);
+            return Err(pixels.invalid_data(err));
+        }
+        pixels.parse_chunk(b1)?;
 
-    fn parse(&mut self) -> Result<Pixel, Error> {
-        // If we've got a run, just count it down and return the same pixel again
-        if self.run > 0 {
-            self.run -= 1;
-        } else {
-            // Read the first byte, which will contain the tag
-            let b1 = read_u8(&mut self.input)?;
-
-            if (b1 & MASK_2) == INDEX {
-                // If the pixel is indexed, get the value from the lookup table
-                self.px = self.lookup[(b1 ^ INDEX) as usize];
-            } else if (b1 & MASK_3) == RUN_8 {
-                // If the pixel is a short run, get the run length
-                self.run = (b1 & 0x1f) as u16;
-            } else if (b1 & MASK_3) == RUN_16 {
-                // If the pixel is a long run, get the run length
-                let b2 = read_u8(&mut self.input)?;
-                self.run = ((((b1 & 0x1f) as u16) << 8) | (b2 as u16)) + 32;
-            } else if (b1 & MASK_2) == DIFF_8 {
-                self.px.r = self.px.r.wrapping_add(((b1 >> 4) & 0x03).wrapping_sub(2));
-                self.px.g = self.px.g.wrapping_add(((b1 >> 2) & 0x03).wrapping_sub(2));
-                self.px.b = self.px.b.wrapping_add((b1 & 0x03).wrapping_sub(2));
-            } else if (b1 & MASK_3) == DIFF_16 {
-                let b2 = read_u8(&mut self.input)?;
-                self.px.r = self.px.r.wrapping_add((b1 & 0x1f).wrapping_sub(16));
-                self.px.g = self.px.g.wrapping_add((b2 >> 4).wrapping_sub(8));
-                self.px.b = self.px.b.wrapping_add((b2 & 0x0f).wrapping_sub(8));
-            } else if (b1 & MASK_4) == DIFF_24 {
-                let [b2, b3] = read::<R, 2>(&mut self.input)?;
-                self.px.r = self
-                    .px
-                    .r
-                    .wrapping_add((((b1 & 0x0f) << 1) | (b2 >> 7)).wrapping_sub(16));
-                self.px.g = self.px.g.wrapping_add(((b2 & 0x7c) >> 2).wrapping_sub(16));
-                self.px.b = self
-                    .px
-                    .b
-                    .wrapping_add((((b2 & 0x03) << 3) | ((b3 & 0xe0) >> 5)).wrapping_sub(16));
-                self.px.a = self.px.a.wrapping_add((b3 & 0x1f).wrapping_sub(16));
-            } else if (b1 & MASK_4) == COLOR {
-                if (b1 & 8) != 0 {
-                    self.px.r = read_u8(&mut self.input)?;
+        // The differences can be worked out from the pixel it decoded to
+        let px = pixels.px;
+        let diff = |a: u8, b: u8| a.wrapping_sub(b) as i8;
+        let op = match b1 {
+            OP_RGB => Op::Rgb(px),
+            OP_RGBA => Op::Rgba(px),
+            _ => match b1 & MASK_2 {
+                OP_INDEX => Op::Index(b1),
+                OP_DIFF => Op::Diff {
+                    dr: diff(px.r, prev.r),
+                    dg: diff(px.g, prev.g),
+                    db: diff(px.b, prev.b),
+                },
+                OP_LUMA => {
+                    let dg = diff(px.g, prev.g);
+                    Op::Luma {
+                        dg,
+                        dr_dg: diff(px.r, prev.r).wrapping_sub(dg),
+                        db_dg: diff(px.b, prev.b).wrapping_sub(dg),
+                    }
                 }
-                if (b1 & 4) != 0 {
-                    self.px.g = read_u8(&mut self.input)?;
-                }
-                if (b1 & 2) != 0 {
-                    self.px.b = read_u8(&mut self.input)?;
-                }
-                if (b1 & 1) != 0 {
-                    self.px.a = read_u8(&mut self.input)?;
-                }
-            }
+                _ => Op::Run(pixels.run + 1),
+            },
+        };
 
-            // Put the new pixel into the lookup table
-            self.lookup[(self.px.hash() % 64) as usize] = self.px;
+        // Count off every pixel the chunk produces, including all of a run
+        let n = pixels.run as usize + 1;
+        if n > pixels.remaining {
+            return Err(pixels.invalid_data(Error::TooMuchData));
         }
-
-        self.remaining -= 1;
-        Ok(self.px)
+        pixels.run = 0;
+        pixels.remaining -= n;
+        if pixels.remaining == 0 {
+            pixels.check_end_marker()?;
+        }
+        Ok((offset, op))
     }
 }
 
-impl<R> Iterator for Pixels<R>
+impl<R> Iterator for Chunks<R>
 where
     R: Read,
 {
-    type Item = Result<Pixel, Error>;
+    type Item = Result<(usize, Op), Error>;
 
     #[inline]
     fn next(&mut self) -> Option<Self::Item> {
-        (self.remaining > 0).then(|| {
-            let result = self.parse();
-
-            // If we get an error while parsing, end the iterator
-            if result.is_err() {
-                self.remaining = 0;
-            }
-
-            result
-        })
+        (self.pixels.remaining > 0).then(|| self.parse().map_err(|err| self.pixels.fail(err)))
     }
 }
 
+impl<R> FusedIterator for Chunks<R> where R: Read {}
+
 /// An iterator that parses pixels from the encoded image's data block.
 /// If the parser encounters an error, this iterator will panic.
 pub struct Unwrapped<'a, I> {
@@ -246,10 +1858,22 @@ where
 
     #[inline]
     fn next(&mut self) -> Option<Self::Item> {
-        self.pixels.next().and_then(|p| Some(p.unwrap()))
+        self.pixels.next().map(|p| p.unwrap())
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.pixels.size_hint()
     }
 }
 
+impl<'a, P, I> ExactSizeIterator for Unwrapped<'a, I> where
+    I: ExactSizeIterator<Item = Result<P, Error>>
+{
+}
+
+impl<'a, P, I> FusedIterator for Unwrapped<'a, I> where I: FusedIterator<Item = Result<P, Error>> {}
+
 /// An iterator that parses pixels from the encoded image's data block.
 /// If the parser fails, this iterator will discard the error and finish.
 /// In this event, it is up to the user to check if the correct amount
@@ -278,8 +1902,16 @@ where
     fn next(&mut self) -> Option<Self::Item> {
         self.pixels.next().and_then(Result::ok)
     }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        // Any of the pixels could be an error, which ends this iterator right away
+        (0, self.pixels.size_hint().1)
+    }
 }
 
+impl<'a, P, I> FusedIterator for Okay<'a, I> where I: FusedIterator<Item = Result<P, Error>> {}
+
 pub struct Positioned<'a, I> {
     pixels: &'a mut I,
     width: usize,
@@ -306,14 +1938,603 @@ where
 
     #[inline]
     fn next(&mut self) -> Option<Self::Item> {
-        self.pixels.next().and_then(|p| {
+        self.pixels.next().map(|p| {
             let (x, y) = (self.x, self.y);
             self.x += 1;
             if self.x == self.width {
                 self.x = 0;
                 self.y += 1;
             }
-            Some((x, y, p))
+            (x, y, p)
         })
     }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.pixels.size_hint()
+    }
+}
+
+impl<'a, P, I> ExactSizeIterator for Positioned<'a, I> where I: ExactSizeIterator<Item = P> {}
+
+impl<'a, P, I> FusedIterator for Positioned<'a, I> where I: FusedIterator<Item = P> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{SPEC_IMAGE, SPEC_PIXELS};
+    use crate::{encode_to_vec, encode_with_options, EncodeOptions};
+
+    /// The header of a `width` by `height` RGBA image, followed by `data`.
+    fn image(width: u32, height: u32, data: &[u8]) -> Vec<u8> {
+        let mut bytes = Vec::from(*b"qoif");
+        bytes.extend_from_slice(&width.to_be_bytes());
+        bytes.extend_from_slice(&height.to_be_bytes());
+        bytes.extend_from_slice(&[4, 0]);
+        bytes.extend_from_slice(data);
+        bytes
+    }
+
+    /// Encode `pixels` as a single row, in the format `legacy` says.
+    fn encode_row(pixels: &[Pixel], legacy: bool) -> Vec<u8> {
+        let width = NonZeroUsize::new(pixels.len()).unwrap();
+        let height = NonZeroUsize::new(1).unwrap();
+        let mut data = Vec::new();
+        let options = EncodeOptions {
+            legacy,
+            ..Default::default()
+        };
+        encode_with_options(width, height, pixels.iter().copied(), &mut data, options).unwrap();
+        data
+    }
+
+    /// A few pixels that don't all encode the same way.
+    fn gradient(n: u8) -> Vec<Pixel> {
+        (0..n)
+            .map(|i| Pixel::rgba(i * 3, 50, 255 - i, 255))
+            .collect()
+    }
+
+    #[test]
+    fn decodes_the_spec_fixture() {
+        let (width, height, pixels, all_opaque) =
+            Decoder::new().decode_to_vec(&SPEC_IMAGE[..]).unwrap();
+        assert_eq!((width, height, all_opaque), (4, 2, None));
+        assert_eq!(pixels, SPEC_PIXELS);
+
+        // Every chunk should be read as the opcode the fixture says it is
+        let chunks: Result<Vec<_>, _> = decode_chunks(&SPEC_IMAGE[..]).unwrap().collect();
+        assert_eq!(
+            chunks.unwrap(),
+            [
+                (14, Op::Rgb(Pixel::rgba(10, 20, 30, 255))),
+                (
+                    18,
+                    Op::Diff {
+                        dr: 1,
+                        dg: -1,
+                        db: 1
+                    }
+                ),
+                (19, Op::Run(2)),
+                (
+                    20,
+                    Op::Luma {
+                        dg: 6,
+                        dr_dg: 3,
+                        db_dg: -7
+                    }
+                ),
+                (22, Op::Index(9)),
+                (23, Op::Rgba(Pixel::rgba(255, 0, 30, 128))),
+                (
+                    28,
+                    Op::Diff {
+                        dr: 1,
+                        dg: -1,
+                        db: 0
+                    }
+                ),
+            ]
+        );
+    }
+
+    /// Small enough to run quickly under `cargo +nightly miri test`, which checks the
+    /// arrays that the header and chunks are read into.
+    #[test]
+    fn decodes_a_small_image() {
+        let mut data = image(2, 2, &[OP_RGBA, 10, 20, 30, 40, OP_RUN, OP_RGB, 1, 2, 3]);
+        data.extend_from_slice(&[OP_INDEX | 12]);
+        data.extend_from_slice(&END_MARKER);
+
+        let (width, height, pixels) = decode(&data[..]).unwrap();
+        assert_eq!((width, height), (2, 2));
+        let pixels: Result<Vec<_>, _> = pixels.collect();
+        let first = Pixel::rgba(10, 20, 30, 40);
+        assert_eq!(
+            pixels.unwrap(),
+            [first, first, Pixel::rgba(1, 2, 3, 40), first]
+        );
+        assert!(matches!(
+            decode(&data[..9]),
+            Err(Error::UnexpectedEof { offset: 9 })
+        ));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn invalid_data_doesnt_drain_endless_streams() {
+        // A run of 62 pixels in a 1x1 image, followed by a stream that never ends
+        let data = image(1, 1, &[OP_RUN | 61]);
+        let input = std::io::Read::chain(&data[..], std::io::repeat(OP_RGB));
+        let (_, _, mut pixels) = decode(input).unwrap();
+        assert!(matches!(pixels.next(), Some(Err(Error::TooMuchData))));
+        assert!(pixels.next().is_none());
+    }
+
+    #[test]
+    fn invalid_data_leaves_following_images_alone() {
+        // An image whose end marker comes two pixels early, followed by another image
+        let mut data = image(3, 1, &[OP_RGB, 1, 2, 3]);
+        data.extend_from_slice(&END_MARKER);
+        let next = encode_to_vec(
+            NonZeroUsize::new(1).unwrap(),
+            NonZeroUsize::new(1).unwrap(),
+            [Pixel::rgb(1, 2, 3)],
+        )
+        .unwrap();
+        data.extend_from_slice(&next);
+
+        let decoder = Decoder::new().read_buffer(16);
+        let (_, _, mut pixels) = decoder.decode(&data[..]).unwrap();
+        assert!(matches!(pixels.next(), Some(Ok(_))));
+        assert!(matches!(
+            pixels.next(),
+            Some(Err(Error::Truncated {
+                expected: 3,
+                got: 1
+            }))
+        ));
+        assert!(!pixels.input.is_empty());
+    }
+
+    #[test]
+    fn checks_what_follows_the_last_pixel() {
+        let pixels = gradient(20);
+        let data = encode_row(&pixels, false);
+        let body = &data[..data.len() - END_MARKER.len()];
+        let decode_with = |tail: &[u8]| {
+            let mut data = body.to_vec();
+            data.extend_from_slice(tail);
+            Decoder::new()
+                .decode_to_vec(&data[..])
+                .map(|(_, _, pixels, _)| pixels)
+        };
+
+        assert_eq!(decode_with(&END_MARKER).unwrap(), pixels);
+        assert_eq!(decode_with(&[]).unwrap(), pixels);
+        assert!(matches!(
+            decode_with(&[0, 0, 0, 0]),
+            Err(Error::Truncated {
+                expected: 20,
+                got: 20
+            })
+        ));
+        assert!(matches!(
+            decode_with(&[1, 2, 3, 4, 5, 6, 7, 8]),
+            Err(Error::TooMuchData)
+        ));
+        assert!(matches!(
+            decode_with(&[0, 0, 0, 1, 1, 2, 3, 4]),
+            Err(Error::TooMuchData)
+        ));
+    }
+
+    #[test]
+    fn all_opaque_needs_a_good_end_marker() {
+        let data = encode_row(&gradient(20), false);
+        let all_opaque = |data: &[u8]| {
+            let (_, _, mut pixels) = decode(data).unwrap();
+            pixels.by_ref().for_each(drop);
+            pixels.all_opaque()
+        };
+
+        assert_eq!(all_opaque(&data), Some(true));
+        let mut bad = data.clone();
+        *bad.last_mut().unwrap() = 2;
+        assert_eq!(all_opaque(&bad), None);
+        assert_eq!(all_opaque(&data[..data.len() - 2]), None);
+    }
+
+    #[test]
+    fn skipping_past_the_end_checks_the_end_marker() {
+        // The gradient ends in a full color chunk, and the spec fixture in a run
+        for data in [encode_row(&gradient(8), false), SPEC_IMAGE.to_vec()] {
+            let (_, _, mut pixels) = decode(&data[..]).unwrap();
+            assert!(pixels.nth(8).is_none());
+            assert!(pixels.verify_end_marker().is_ok());
+            assert!(pixels.all_opaque().is_some());
+        }
+
+        // Trailing garbage is reported by the skip, rather than silently ignored
+        let mut data = encode_row(&gradient(8), false);
+        let end = data.len() - END_MARKER.len();
+        data[end..].copy_from_slice(&[1, 2, 3, 4, 5, 6, 7, 8]);
+        let (_, _, mut pixels) = decode(&data[..]).unwrap();
+        assert!(matches!(pixels.nth(8), Some(Err(Error::TooMuchData))));
+        assert!(pixels.next().is_none());
+        assert!(pixels.verify_end_marker().is_err());
+    }
+
+    #[test]
+    fn counts_the_pixels_decoded_before_an_error() {
+        // A 10x1 image with a run that's too long after 2 pixels
+        let data = image(10, 1, &[OP_RGB, 1, 2, 3, OP_DIFF, OP_RUN | 20]);
+        let (_, _, mut pixels) = decode(&data[..]).unwrap();
+        assert_eq!(pixels.pixels_decoded(), 0);
+        pixels.next().unwrap().unwrap();
+        assert_eq!((pixels.pixels_decoded(), pixels.remaining()), (1, 9));
+        pixels.next().unwrap().unwrap();
+        assert!(pixels.next().unwrap().is_err());
+        assert_eq!((pixels.pixels_decoded(), pixels.remaining()), (2, 0));
+        assert!(pixels.next().is_none());
+        assert_eq!(pixels.pixels_decoded(), 2);
+
+        // Every pixel was decoded before the end marker turned out to be wrong
+        let mut data = encode_row(&gradient(20), false);
+        *data.last_mut().unwrap() = 2;
+        let (_, _, mut pixels) = decode(&data[..]).unwrap();
+        assert!(pixels.by_ref().last().unwrap().is_err());
+        assert_eq!(pixels.pixels_decoded(), 20);
+    }
+
+    #[test]
+    fn resumes_from_saved_states() {
+        let pixels = gradient(20);
+        let data = encode_row(&pixels, false);
+        let (_, _, mut decoder) = decode(&data[..]).unwrap();
+        let first: Vec<_> = decoder.by_ref().take(7).map(Result::unwrap).collect();
+        let state = decoder.save_state();
+
+        // Pick up from the snapshot with a fresh decoder and reader
+        let (_, _, mut resumed) = decode(&data[..]).unwrap();
+        *resumed.get_mut() = &data[state.offset()..];
+        resumed.restore_state(state.clone()).unwrap();
+        assert_eq!(resumed.remaining(), 13);
+        let rest: Vec<_> = resumed.map(Result::unwrap).collect();
+        assert_eq!([first, rest].concat(), pixels);
+
+        // Snapshots only fit decoders for the same kind of image
+        let other = encode_row(&pixels[..10], false);
+        let (_, _, mut smaller) = decode(&other[..]).unwrap();
+        assert!(matches!(
+            smaller.restore_state(state.clone()),
+            Err(Error::DimensionMismatch {
+                expected: (10, 1),
+                found: (20, 1)
+            })
+        ));
+        assert_eq!(smaller.remaining(), 10);
+        let legacy = encode_row(&pixels, true);
+        let (_, _, mut legacy) = Decoder::new().legacy(true).decode(&legacy[..]).unwrap();
+        assert!(matches!(
+            legacy.restore_state(state),
+            Err(Error::StateMismatch)
+        ));
+    }
+
+    /// Check that the items left in `iter` are always within the bounds of its size hint.
+    fn check_size_hints<I: Iterator + Clone>(mut iter: I) {
+        loop {
+            let (lower, upper) = iter.size_hint();
+            let rest = iter.clone().count();
+            assert!(lower <= rest && upper.is_none_or(|upper| rest <= upper));
+            if iter.next().is_none() {
+                break;
+            }
+        }
+    }
+
+    #[test]
+    fn size_hints_allow_for_errors() {
+        // A 10x1 image with a run that's too long after 2 pixels
+        let data = image(10, 1, &[OP_RGB, 1, 2, 3, OP_DIFF, OP_RUN | 20]);
+        let (_, _, pixels) = decode(&data[..]).unwrap();
+        assert_eq!(pixels.size_hint(), (1, Some(10)));
+        assert_eq!(pixels.clone().count(), 3);
+        assert_eq!(pixels.clone().ok().count(), 2);
+        assert_eq!(pixels.clone().ok().size_hint(), (0, Some(10)));
+        assert_eq!(pixels.clone().annotated().count(), 3);
+        check_size_hints(pixels);
+
+        let data = encode_row(&gradient(20), false);
+        let (_, _, pixels) = decode(&data[..]).unwrap();
+        assert_eq!(pixels.size_hint(), (1, Some(20)));
+        check_size_hints(pixels);
+    }
+
+    /// A path in the temporary directory that no other test uses.
+    #[cfg(feature = "memmap2")]
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("qoi-test-{}-{}", std::process::id(), name))
+    }
+
+    #[cfg(feature = "memmap2")]
+    #[test]
+    fn decodes_into_memory_maps() {
+        let (input, output) = (temp_path("mmap.qoi"), temp_path("mmap.rgba"));
+        let pixels = gradient(20);
+        std::fs::write(&input, encode_row(&pixels, false)).unwrap();
+        assert_eq!(decode_file_to_mmap(&input, &output).unwrap(), (20, 1));
+        let bytes: Vec<u8> = pixels.iter().flat_map(|&p| <[u8; 4]>::from(p)).collect();
+        assert_eq!(std::fs::read(&output).unwrap(), bytes);
+        std::fs::remove_file(input).unwrap();
+        std::fs::remove_file(output).unwrap();
+    }
+
+    #[cfg(feature = "memmap2")]
+    #[test]
+    fn memory_maps_only_grow_with_the_data() {
+        let (input, output) = (temp_path("huge.qoi"), temp_path("huge.rgba"));
+        std::fs::write(&input, image(60000, 60000, &[OP_RGB, 1, 2, 3])).unwrap();
+        assert!(decode_file_to_mmap(&input, &output).is_err());
+        let len = std::fs::metadata(&output).unwrap().len();
+        assert!(len <= (MAX_PREALLOC * 4) as u64);
+
+        // The pixel count fits in a 64-bit usize, but their bytes don't
+        #[cfg(target_pointer_width = "64")]
+        {
+            std::fs::write(&input, image(u32::MAX, u32::MAX, &[])).unwrap();
+            assert!(matches!(
+                decode_file_to_mmap(&input, &output),
+                Err(Error::ImageTooLarge { .. })
+            ));
+        }
+        std::fs::remove_file(input).unwrap();
+        std::fs::remove_file(output).unwrap();
+    }
+
+    #[test]
+    fn detects_small_files_in_the_other_format() {
+        let pixels = gradient(20);
+        let legacy = encode_row(&pixels, true);
+        let spec = encode_row(&pixels, false);
+        assert!(matches!(
+            Decoder::new().decode_to_vec(&legacy[..]),
+            Err(Error::UnsupportedQoiVersion)
+        ));
+        assert!(matches!(
+            Decoder::new().legacy(true).decode_to_vec(&spec[..]),
+            Err(Error::UnsupportedQoiVersion)
+        ));
+    }
+
+    /// Encode a `width` by `height` image of `pixels`.
+    fn encode_image(width: usize, height: usize, pixels: &[Pixel]) -> Vec<u8> {
+        let width = NonZeroUsize::new(width).unwrap();
+        let height = NonZeroUsize::new(height).unwrap();
+        encode_to_vec(width, height, pixels.iter().copied()).unwrap()
+    }
+
+    #[test]
+    fn decodes_images_of_the_expected_size() {
+        let pixels = gradient(12);
+        let data = encode_image(4, 3, &pixels);
+        assert_eq!(decode_expecting(&data[..], 4, 3).unwrap(), pixels);
+        assert!(matches!(
+            decode_expecting(&data[..], 3, 4),
+            Err(Error::DimensionMismatch {
+                expected: (3, 4),
+                found: (4, 3)
+            })
+        ));
+    }
+
+    #[test]
+    fn decodes_ranges_of_rows() {
+        // Runs and indexes cross the rows that get skipped
+        let [a, b, c] = [
+            Pixel::rgb(9, 9, 9),
+            Pixel::rgb(200, 0, 0),
+            Pixel::rgb(0, 0, 200),
+        ];
+        let pixels = [a, a, a, a, b, b, b, c, a, c, c, c];
+        let data = encode_image(3, 4, &pixels);
+        assert_eq!(decode_row_range(&data[..], 1, 3).unwrap(), pixels[3..9]);
+        assert_eq!(decode_row_range(&data[..], 0, 1).unwrap(), pixels[..3]);
+        assert_eq!(decode_row_range(&data[..], 2, 10).unwrap(), pixels[6..]);
+        assert!(decode_row_range(&data[..], 3, 2).unwrap().is_empty());
+    }
+
+    #[test]
+    fn planes_recombine_into_the_image() {
+        let data = encode_row(&gradient(20), false);
+        let (w, h, [r, g, b, a]) = decode_planar(&data[..]).unwrap();
+        let recombined: Vec<_> = (0..w * h)
+            .map(|i| Pixel::rgba(r[i], g[i], b[i], a[i]))
+            .collect();
+
+        let mut pixels = Vec::new();
+        assert_eq!(decode_into_vec(&data[..], &mut pixels).unwrap(), (w, h));
+        assert_eq!(recombined, pixels);
+    }
+
+    #[test]
+    fn downsamples_to_the_max_size() {
+        let pixels: Vec<_> = (0..400 * 400)
+            .map(|i| Pixel::gray(((i % 400) / 4) as u8))
+            .collect();
+        let data = encode_image(400, 400, &pixels);
+        let (w, h, thumb) = decode_downsampled(&data[..], 100).unwrap();
+        assert_eq!((w, h, thumb.len()), (100, 100, 100 * 100));
+        assert!(thumb[..100]
+            .iter()
+            .enumerate()
+            .all(|(x, &px)| px == Pixel::gray(x as u8)));
+
+        // Sides that don't divide evenly are rounded up
+        let data = encode_image(5, 3, &pixels[..15]);
+        let (w, h, _) = decode_downsampled(&data[..], 2).unwrap();
+        assert_eq!((w, h), (2, 1));
+    }
+
+    #[test]
+    fn drops_alpha_when_forced_to_rgb() {
+        let pixels = [Pixel::rgba(1, 2, 3, 0), Pixel::rgba(4, 5, 6, 128)].repeat(6);
+        let data = encode_image(4, 3, &pixels);
+        let (w, h, rgb) = decode_to_rgb8_forced(&data[..]).unwrap();
+        assert_eq!(rgb.len(), w * h * 3);
+        assert_eq!(rgb[..6], [1, 2, 3, 4, 5, 6]);
+    }
+
+    #[test]
+    fn rows_flatten_into_the_image() {
+        let pixels = gradient(12);
+        let data = encode_image(4, 3, &pixels);
+        let (_, _, decoder) = decode(&data[..]).unwrap();
+        let rows: Vec<Vec<Pixel>> = decoder.into_row_iter().map(Result::unwrap).collect();
+        assert_eq!(rows.len(), 3);
+        assert!(rows.iter().all(|row| row.len() == 4));
+
+        let mut image = Vec::new();
+        decode_into_vec(&data[..], &mut image).unwrap();
+        assert_eq!(rows.concat(), image);
+    }
+
+    #[test]
+    fn decodes_with_a_small_read_buffer() {
+        let pixels: Vec<_> = (0..256 * 256u32)
+            .map(|i| {
+                Pixel::rgba(
+                    i as u8,
+                    (i >> 8) as u8,
+                    (i * 7 / 8) as u8,
+                    255 - (i >> 10) as u8,
+                )
+            })
+            .collect();
+        let data = encode_image(256, 256, &pixels);
+        let (w, h, decoded, _) = Decoder::new()
+            .read_buffer(64)
+            .decode_to_vec(&data[..])
+            .unwrap();
+        assert_eq!((w, h), (256, 256));
+        assert_eq!(decoded, pixels);
+    }
+
+    #[test]
+    fn detects_opaque_images() {
+        let decoder = Decoder::new().detect_opaque(true);
+        let opaque = encode_row(&gradient(20), false);
+        assert_eq!(decoder.decode_to_vec(&opaque[..]).unwrap().3, Some(true));
+
+        let mut pixels = gradient(20);
+        pixels[13].a = 254;
+        let transparent = encode_row(&pixels, false);
+        assert_eq!(
+            decoder.decode_to_vec(&transparent[..]).unwrap().3,
+            Some(false)
+        );
+        assert_eq!(Decoder::new().decode_to_vec(&opaque[..]).unwrap().3, None);
+    }
+
+    #[test]
+    fn rejects_alpha_in_strict_rgb_images() {
+        let mut data = image(2, 1, &[OP_RGB, 1, 2, 3, OP_RGBA, 1, 2, 3, 128]);
+        data[12] = 3;
+        data.extend_from_slice(&END_MARKER);
+
+        let strict = Decoder::new().strict_channels(true);
+        assert!(matches!(
+            strict.decode_to_vec(&data[..]),
+            Err(Error::AlphaInRgbImage)
+        ));
+        assert!(Decoder::new().decode_to_vec(&data[..]).is_ok());
+
+        // Storing an alpha of 255 is allowed, since it doesn't change anything
+        data[HEADER_SIZE + 8] = 255;
+        assert!(strict.decode_to_vec(&data[..]).is_ok());
+    }
+
+    #[cfg(feature = "half")]
+    #[test]
+    fn decodes_to_half_floats() {
+        let data = encode_row(&[Pixel::rgba(0, 255, 51, 128)], false);
+        let (w, h, output) = decode_to_rgba16f(&data[..]).unwrap();
+        assert_eq!((w, h), (1, 1));
+        let values: Vec<f32> = output.iter().map(|v| v.to_f32()).collect();
+        assert_eq!(values[..2], [0.0, 1.0]);
+        assert!((values[2] - 0.2).abs() < 0.001 && (values[3] - 128.0 / 255.0).abs() < 0.001);
+    }
+
+    #[test]
+    fn annotates_pixels_repeated_by_runs() {
+        let repeats = |pixels: &[Pixel]| -> Vec<bool> {
+            let data = encode_row(pixels, false);
+            let (_, _, mut decoder) = decode(&data[..]).unwrap();
+            let annotated = decoder.annotated().map(|p| p.unwrap().1);
+            annotated.collect()
+        };
+
+        let mut expected = [true; 100];
+        expected[0] = false;
+        assert_eq!(repeats(&[Pixel::gray(3); 100]), expected);
+        let [a, b] = [Pixel::gray(3), Pixel::gray(200)];
+        assert_eq!(repeats(&[a, a, b, b, a]), [false, true, false, true, false]);
+    }
+
+    #[test]
+    fn decodes_to_cairo_surfaces() {
+        let data = encode_row(&[Pixel::rgba(255, 0, 0, 128), Pixel::rgb(1, 2, 3)], false);
+        let (w, h, stride, output) = decode_to_cairo_argb32(&data[..]).unwrap();
+        assert_eq!((w, h, stride), (2, 1, 8));
+
+        // Half-transparent red has its red premultiplied down to half
+        let argb = |i: usize| u32::from_ne_bytes(output[i * 4..i * 4 + 4].try_into().unwrap());
+        assert_eq!(argb(0), 0x8080_0000);
+        assert_eq!(argb(1), 0xff01_0203);
+        if cfg!(target_endian = "little") {
+            assert_eq!(output[..4], [0, 0, 128, 128]);
+        }
+    }
+
+    #[test]
+    fn recovers_pixels_until_the_end_of_the_stream() {
+        let pixels = gradient(12);
+        let mut data = encode_image(4, 3, &pixels);
+        data[4..8].fill(0);
+        assert!(decode(&data[..]).is_err());
+        assert_eq!(decode_until_eof(&data[..]).unwrap(), pixels);
+
+        // Without its end marker, the pixels end where the stream does
+        assert_eq!(
+            decode_until_eof(&data[..data.len() - END_MARKER.len()]).unwrap(),
+            pixels
+        );
+
+        // Streams cut short partway through the end marker still end with the last pixel
+        for cut in 1..END_MARKER.len() {
+            assert_eq!(
+                decode_until_eof(&data[..data.len() - cut]).unwrap(),
+                pixels,
+                "{cut} bytes cut"
+            );
+        }
+
+        // A stream cut short partway through a chunk ends with the chunk before it. The
+        // gradient is stored as full colors after the first pixel, because its alpha
+        // changes.
+        let pixels: Vec<Pixel> = (0..4).map(|i| Pixel::rgba(i * 60, 0, 0, i * 60)).collect();
+        let mut data = encode_image(4, 1, &pixels);
+        data[4..8].fill(0);
+        let chunks_end = data.len() - END_MARKER.len();
+        assert_eq!(data[chunks_end - 5], OP_RGBA);
+        for cut in 1..5 {
+            assert_eq!(
+                decode_until_eof(&data[..chunks_end - cut]).unwrap(),
+                pixels[..3],
+                "{cut} bytes cut"
+            );
+        }
+    }
 }
@@ -1,14 +1,800 @@
-use crate::{consts::*, Error, Pixel};
-use std::io::Write;
-use std::num::NonZeroUsize;
+use crate::io::{self, Write};
+use crate::{consts::*, Channels, Colorspace, Error, Pixel};
+use alloc::vec::Vec;
+use core::cell::Cell;
+use core::num::NonZeroUsize;
+
+/// Options for customizing how an image is encoded.
+#[derive(Copy, Clone, Debug)]
+pub struct EncodeOptions {
+    /// Pre-populate the encoder's running lookup table with these pixels, rather than
+    /// starting from an empty one. When encoding many similar images (like tiles that
+    /// share a palette), this lets the first occurrence of each common color be stored
+    /// as a 1-byte index instead of a full color. Each pixel only helps if it sits in the
+    /// slot matching its [`Pixel::index`], so use [`seed_lookup_table`] to build the table.
+    ///
+    /// **This produces non-standard files.** They can only be decoded correctly by a
+    /// [`Decoder`](crate::Decoder) configured with the exact same table via
+    /// [`Decoder::seed_lookup`](crate::Decoder::seed_lookup).
+    pub seed_lookup: Option<[Pixel; 64]>,
+
+    /// Encode the image twice, once in the usual row-major order and once in serpentine
+    /// order (where every odd row is stored right-to-left), and keep whichever is smaller.
+    /// When the end of one row tends to match the end of the next, like in images made of
+    /// vertical stripes or tiles, serpentine order lets runs continue across rows.
+    ///
+    /// This buffers the whole image and its encoded data in memory. **If serpentine order
+    /// is picked, this produces non-standard files**, which are marked with a flag in the
+    /// header so that this crate's [`Decoder`](crate::Decoder) can restore the row order.
+    pub reorder_for_runs: bool,
+
+    /// Encode the image in the pre-release draft format, which older versions of this
+    /// crate wrote, instead of the released QOI specification. This is only useful for
+    /// programs that still read the old format, and the files have to be decoded with
+    /// [`Decoder::legacy`](crate::Decoder::legacy).
+    ///
+    /// The draft format hashes pixels differently, so a table from [`seed_lookup_table`]
+    /// won't have its colors in the slots the encoder looks for them in. The file is
+    /// still decoded correctly, but won't be any smaller.
+    pub legacy: bool,
+
+    /// Store the rows of the image bottom-to-top, for pixels that are supplied in the
+    /// bottom-up row order that OpenGL textures use. The iterator is still read in the
+    /// order it's supplied, so the first row it yields ends up at the bottom of the image.
+    ///
+    /// Flipping the rows needs the whole image to be buffered in memory before any of
+    /// it can be encoded.
+    pub flip_vertically: bool,
+
+    /// The channels to mark the image as having in its header, which is RGBA by default.
+    /// For [`Channels::Rgb`], every pixel is encoded as fully opaque, so no alpha is ever
+    /// stored.
+    pub channels: Channels,
+
+    /// The color space to mark the image as having in its header, which is sRGB by
+    /// default. This doesn't change how the pixels are encoded.
+    pub colorspace: Colorspace,
+
+    /// What to do if the iterator runs out of pixels before the image is complete. By
+    /// default, encoding fails with [`Error::IteratorEmpty`].
+    pub on_short: ShortPolicy,
+}
+
+impl Default for EncodeOptions {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            seed_lookup: None,
+            reorder_for_runs: false,
+            legacy: false,
+            flip_vertically: false,
+            channels: Channels::Rgba,
+            colorspace: Colorspace::Srgb,
+            on_short: ShortPolicy::Error,
+        }
+    }
+}
+
+/// What the encoder does when it's given fewer than `width * height` pixels. See
+/// [`EncodeOptions::on_short`].
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub enum ShortPolicy {
+    /// Fail with [`Error::IteratorEmpty`], leaving a partially written image in the
+    /// output.
+    #[default]
+    Error,
+
+    /// Fill the rest of the image with this pixel. The output is still a valid image of
+    /// the full `width * height` size, with the padding after the last pixel supplied.
+    Pad(Pixel),
+}
+
+/// Build a running lookup table pre-populated with `colors`, for use with
+/// [`EncodeOptions::seed_lookup`]. Each color is placed in the slot the encoder will
+/// look for it in. If several colors share a slot, the last one wins.
+pub fn seed_lookup_table(colors: &[Pixel]) -> [Pixel; 64] {
+    let mut lookup = [Pixel::transparent(); 64];
+    for &px in colors {
+        lookup[px.index() as usize] = px;
+    }
+    lookup
+}
 
 /// Encodes the pixels supplied by the `pixels` iterator into the `output` stream. The iterator is
 /// expected to have `width * height` pixels in it. Returns the size of the encoded data.
+///
+/// Anything that can be iterated over to get pixels can be passed in, like a `Vec` of them
+/// or an iterator over a slice's copied pixels.
+#[inline]
 pub fn encode<I, W>(
+    width: NonZeroUsize,
+    height: NonZeroUsize,
+    pixels: I,
+    output: W,
+) -> Result<usize, Error>
+where
+    I: IntoIterator<Item = Pixel>,
+    W: Write,
+{
+    encode_with_options(width, height, pixels, output, EncodeOptions::default())
+}
+
+/// Encodes the pixels supplied by the `pixels` iterator into a new vector of bytes. The
+/// iterator is expected to have `width * height` pixels in it. The vector is allocated
+/// with enough room for the largest image this could produce, so it never grows. If
+/// there isn't enough memory for that, this fails with [`Error::ImageTooLarge`].
+pub fn encode_to_vec<I>(
+    width: NonZeroUsize,
+    height: NonZeroUsize,
+    pixels: I,
+) -> Result<Vec<u8>, Error>
+where
+    I: IntoIterator<Item = Pixel>,
+{
+    let mut output = output_vec(width, height)?;
+    encode(width, height, pixels, &mut output)?;
+    Ok(output)
+}
+
+/// Encodes the pixels supplied by the `pixels` iterator into the `output` stream, using the
+/// provided options. The iterator is expected to have `width * height` pixels in it, unless
+/// [`EncodeOptions::on_short`] says to pad the image. Returns the size of the encoded data.
+#[inline]
+pub fn encode_with_options<I, W>(
+    width: NonZeroUsize,
+    height: NonZeroUsize,
+    pixels: I,
+    output: W,
+    options: EncodeOptions,
+) -> Result<usize, Error>
+where
+    I: IntoIterator<Item = Pixel>,
+    W: Write,
+{
+    encode_impl(width, height, pixels.into_iter(), output, options)
+}
+
+/// Encodes the pixels supplied by the `pixels` iterator into the `output` stream, calling
+/// `should_cancel` before each row of pixels. If it returns `true`, encoding stops and
+/// [`Error::Cancelled`] is returned, leaving a partially written image in `output`.
+/// Otherwise, this behaves just like [`encode`].
+pub fn encode_cancellable<I, W, F>(
+    width: NonZeroUsize,
+    height: NonZeroUsize,
+    pixels: I,
+    output: W,
+    should_cancel: F,
+) -> Result<usize, Error>
+where
+    I: IntoIterator<Item = Pixel>,
+    W: Write,
+    F: Fn() -> bool,
+{
+    // Cut the iterator short if we're cancelled at the start of a row
+    let cancelled = Cell::new(false);
+    let pixels = pixels.into_iter().enumerate().map_while(|(i, px)| {
+        if i % width.get() == 0 && should_cancel() {
+            cancelled.set(true);
+            None
+        } else {
+            Some(px)
+        }
+    });
+    match encode_impl(width, height, pixels, output, EncodeOptions::default()) {
+        Err(Error::IteratorEmpty { .. }) if cancelled.get() => Err(Error::Cancelled),
+        result => result,
+    }
+}
+
+/// Calculate the exact size that encoding `pixels` (in row-major order) would produce,
+/// without writing the encoded data anywhere. The size doesn't depend on the image's
+/// width or height, only on its pixels. If there are no pixels, this is just the size
+/// of the header and end marker.
+///
+/// The pixels are measured as a single row with [`encoded_size`], so this fails with
+/// [`Error::ImageTooLarge`] if there are more of them than fit in a header's width.
+pub fn estimate_encoded_size(pixels: &[Pixel]) -> Result<usize, Error> {
+    match NonZeroUsize::new(pixels.len()) {
+        Some(len) => encoded_size(len, NonZeroUsize::MIN, pixels.iter().copied()),
+        None => Ok(HEADER_SIZE + END_MARKER.len()),
+    }
+}
+
+/// Calculate the exact size that encoding the pixels supplied by the `pixels` iterator
+/// would produce, by running the encoder without keeping any of the encoded data. The
+/// iterator is expected to have `width * height` pixels in it.
+pub fn encoded_size<I>(width: NonZeroUsize, height: NonZeroUsize, pixels: I) -> Result<usize, Error>
+where
+    I: IntoIterator<Item = Pixel>,
+{
+    let mut sink = CountingSink::new();
+    encode(width, height, pixels, &mut sink)?;
+    Ok(sink.count())
+}
+
+/// A writer that discards everything written to it, but keeps count of how many bytes
+/// that was. Encoding into one measures an image's encoded size without storing it.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct CountingSink {
+    count: usize,
+}
+
+impl CountingSink {
+    /// Create a sink that hasn't had any bytes written to it.
+    #[inline]
+    pub const fn new() -> Self {
+        Self { count: 0 }
+    }
+
+    /// The amount of bytes that have been written to the sink.
+    #[inline]
+    pub const fn count(&self) -> usize {
+        self.count
+    }
+}
+
+impl Write for CountingSink {
+    #[inline]
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.count += buf.len();
+        Ok(buf.len())
+    }
+
+    #[inline]
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// The largest size an image of `width` by `height` pixels with `channels` can be
+/// encoded to, which is the same bound the reference implementation uses. This
+/// is enough room to encode any image of that size, no matter what its pixels are.
+/// Returns `None` if the size is too large to fit in a `usize`, instead of wrapping.
+#[inline]
+pub fn max_encoded_size(
+    width: NonZeroUsize,
+    height: NonZeroUsize,
+    channels: Channels,
+) -> Option<usize> {
+    width
+        .get()
+        .checked_mul(height.get())?
+        .checked_mul(channels as usize + 1)?
+        .checked_add(HEADER_SIZE + END_MARKER.len())
+}
+
+/// Allocate a vector with room for the largest size an image of `width` by `height`
+/// pixels could be encoded to. The size is checked before anything is allocated, so an image
+/// too large to encode or to allocate room for fails with [`Error::ImageTooLarge`].
+fn output_vec(width: NonZeroUsize, height: NonZeroUsize) -> Result<Vec<u8>, Error> {
+    let too_large = || Error::ImageTooLarge {
+        width: width.get(),
+        height: height.get(),
+    };
+    num_pixels(width, height)?;
+    let capacity = max_encoded_size(width, height, Channels::Rgba).ok_or_else(too_large)?;
+    let mut output = Vec::new();
+    output
+        .try_reserve_exact(capacity)
+        .map_err(|_| too_large())?;
+    Ok(output)
+}
+
+/// Encodes a single row of pixels, such as a palette or gradient lookup table, into the
+/// `output` stream as an image with a height of 1. Fails with [`Error::NoImageSize`] if
+/// there are no pixels. Returns the size of the encoded data.
+pub fn encode_strip<W>(pixels: &[Pixel], output: W) -> Result<usize, Error>
+where
+    W: Write,
+{
+    let width = NonZeroUsize::new(pixels.len()).ok_or(Error::NoImageSize)?;
+    encode(width, NonZeroUsize::MIN, pixels.iter().copied(), output)
+}
+
+/// Encodes an image stored as separate planes of red, green, blue, and (optionally) alpha
+/// values into the `output` stream. Each plane must have at least `width * height` values.
+/// If there is no alpha plane, every pixel is opaque and the image is marked as having 3
+/// channels. Returns the size of the encoded data.
+pub fn encode_planar<W>(
+    width: NonZeroUsize,
+    height: NonZeroUsize,
+    r: &[u8],
+    g: &[u8],
+    b: &[u8],
+    a: Option<&[u8]>,
+    output: W,
+) -> Result<usize, Error>
+where
+    W: Write,
+{
+    // Make sure every plane has a value for each pixel
+    let needed = num_pixels(width, height)?;
+    for plane in [Some(r), Some(g), Some(b), a].into_iter().flatten() {
+        if plane.len() < needed {
+            return Err(Error::BufferTooSmall {
+                needed,
+                got: plane.len(),
+            });
+        }
+    }
+
+    // Interleave the planes into pixels as we encode them
+    let pixels = (0..needed).map(|i| Pixel::rgba(r[i], g[i], b[i], a.map_or(255, |a| a[i])));
+    let options = EncodeOptions {
+        channels: if a.is_some() {
+            Channels::Rgba
+        } else {
+            Channels::Rgb
+        },
+        ..Default::default()
+    };
+    encode_impl(width, height, pixels, output, options)
+}
+
+/// Encodes an image stored as interleaved RGBA bytes into the `output` stream. `data` must
+/// have at least `width * height * 4` bytes. Returns the size of the encoded data.
+///
+/// This produces the same data as [`encode`], but because the pixels are all in one
+/// slice, runs of them are found by scanning ahead, which is vectorized when the `simd`
+/// feature is enabled.
+pub fn encode_rgba<W>(
+    width: NonZeroUsize,
+    height: NonZeroUsize,
+    data: &[u8],
+    mut output: W,
+) -> Result<usize, Error>
+where
+    W: Write,
+{
+    let data = interleaved(width, height, data, 4)?;
+
+    let mut num_bytes = 0;
+    let mut write = |buf: &[u8]| {
+        num_bytes += buf.len();
+        output.write_all(buf)
+    };
+    write(&header(width.get(), height.get(), Channels::Rgba, 0))?;
+
+    let mut chunks = ChunkEncoder::new([Pixel::transparent(); 64]);
+    let mut i = 0;
+    while i < data.len() {
+        let bytes = [data[i], data[i + 1], data[i + 2], data[i + 3]];
+        let px = Pixel::from(bytes);
+        i += 4;
+        if px != chunks.prev {
+            chunks.push(px, i == data.len(), &mut write)?;
+            continue;
+        }
+
+        // Find where the run ends, and encode it all at once
+        let run = 1 + run_length(&data[i..], bytes);
+        i += (run - 1) * 4;
+        chunks.push_run(run, i == data.len(), &mut write)?;
+    }
+
+    write(&END_MARKER)?;
+    Ok(num_bytes)
+}
+
+/// Encodes an image of `width` by `height` pixels stored as interleaved RGBA bytes into a
+/// new vector of bytes. `rgba` must have at least `width * height * 4` bytes, and fails
+/// with [`Error::NoImageSize`] if either side is 0. This is the same as [`encode_rgba`],
+/// but it only takes and returns types that are cheap to pass across an FFI boundary,
+/// like the one `wasm-bindgen` makes between Rust and JavaScript.
+pub fn encode_all(width: usize, height: usize, rgba: &[u8]) -> Result<Vec<u8>, Error> {
+    let width = NonZeroUsize::new(width).ok_or(Error::NoImageSize)?;
+    let height = NonZeroUsize::new(height).ok_or(Error::NoImageSize)?;
+    let data = interleaved(width, height, rgba, 4)?;
+    let mut output = output_vec(width, height)?;
+    encode_rgba(width, height, data, &mut output)?;
+    Ok(output)
+}
+
+/// Encodes an image stored as interleaved RGB bytes into the `output` stream. `data` must
+/// have at least `width * height * 3` bytes. Every pixel is opaque, and the image is marked
+/// as having 3 channels. Returns the size of the encoded data.
+pub fn encode_rgb<W>(
+    width: NonZeroUsize,
+    height: NonZeroUsize,
+    data: &[u8],
+    output: W,
+) -> Result<usize, Error>
+where
+    W: Write,
+{
+    let data = interleaved(width, height, data, 3)?;
+    let pixels = data.chunks_exact(3).map(|c| Pixel::rgb(c[0], c[1], c[2]));
+    let options = EncodeOptions {
+        channels: Channels::Rgb,
+        ..Default::default()
+    };
+    encode_impl(width, height, pixels, output, options)
+}
+
+/// An encoder that pixels are pushed into one at a time, for when they aren't all
+/// available at once. This is the streaming counterpart of [`encode`], and produces
+/// the same data.
+pub struct Encoder<W> {
+    output: W,
+    chunks: ChunkEncoder,
+    opaque: bool,
+    num_pixels: usize,
+    count: usize,
+    num_bytes: usize,
+}
+
+impl<W> Encoder<W>
+where
+    W: Write,
+{
+    /// Create an encoder for an image of `width` by `height` pixels with `channels`, and
+    /// write its header to `output`. For [`Channels::Rgb`], every pixel is encoded as
+    /// fully opaque.
+    pub fn new(
+        width: NonZeroUsize,
+        height: NonZeroUsize,
+        channels: Channels,
+        output: W,
+    ) -> Result<Self, Error> {
+        Self::with_colorspace(width, height, channels, Colorspace::Srgb, output)
+    }
+
+    /// Create an encoder like [`new`](Self::new), that marks the image as having
+    /// `colorspace` in its header.
+    pub(crate) fn with_colorspace(
+        width: NonZeroUsize,
+        height: NonZeroUsize,
+        channels: Channels,
+        colorspace: Colorspace,
+        mut output: W,
+    ) -> Result<Self, Error> {
+        let num_pixels = num_pixels(width, height)?;
+        output.write_all(&header(
+            width.get(),
+            height.get(),
+            channels,
+            colorspace.into(),
+        ))?;
+        Ok(Self {
+            output,
+            chunks: ChunkEncoder::new([Pixel::transparent(); 64]),
+            opaque: channels == Channels::Rgb,
+            num_pixels,
+            count: 0,
+            num_bytes: HEADER_SIZE,
+        })
+    }
+
+    /// Encode the next pixel of the image. Fails with [`Error::TooMuchData`] if every
+    /// pixel of the image has already been pushed.
+    pub fn push(&mut self, px: Pixel) -> Result<(), Error> {
+        if self.count == self.num_pixels {
+            return Err(Error::TooMuchData);
+        }
+        self.count += 1;
+
+        let px = if self.opaque {
+            Pixel { a: 255, ..px }
+        } else {
+            px
+        };
+        let output = &mut self.output;
+        let num_bytes = &mut self.num_bytes;
+        self.chunks
+            .push(px, self.count == self.num_pixels, &mut |buf: &[u8]| {
+                *num_bytes += buf.len();
+                output.write_all(buf)
+            })?;
+        Ok(())
+    }
+
+    /// Get a mutable reference to the writer the image is being encoded into.
+    #[cfg(feature = "tokio")]
+    #[inline]
+    pub(crate) fn get_mut(&mut self) -> &mut W {
+        &mut self.output
+    }
+
+    /// Write the end marker after the last pixel, returning the size of the encoded
+    /// data. Fails with [`Error::Truncated`] if not every pixel of the image was pushed.
+    pub fn finish(mut self) -> Result<usize, Error> {
+        if self.count < self.num_pixels {
+            return Err(Error::Truncated {
+                expected: self.num_pixels,
+                got: self.count,
+            });
+        }
+        self.output.write_all(&END_MARKER)?;
+        Ok(self.num_bytes + END_MARKER.len())
+    }
+}
+
+/// The state the encoder carries from one pixel to the next, along with a buffer that
+/// each image is encoded into before it's written out. It can be reused by
+/// [`encode_with_state`] to encode many images, like the frames of a video, so that the
+/// buffer only has to grow for the first of them.
+#[derive(Clone, Debug)]
+pub struct EncoderState {
+    chunks: ChunkEncoder,
+    buf: Vec<u8>,
+}
+
+impl EncoderState {
+    /// Create a state with the initial values that every image starts encoding from, and
+    /// an empty buffer.
+    #[inline]
+    pub fn new() -> Self {
+        Self {
+            chunks: ChunkEncoder::new([Pixel::transparent(); 64]),
+            buf: Vec::new(),
+        }
+    }
+
+    /// Restore the initial values that every image starts encoding from. An image has to
+    /// be encoded from these to be decoded correctly, so the state can't carry over from
+    /// one image to the next. The buffer is emptied, but keeps its capacity.
+    #[inline]
+    pub fn reset(&mut self) {
+        self.chunks = ChunkEncoder::new([Pixel::transparent(); 64]);
+        self.buf.clear();
+    }
+}
+
+impl Default for EncoderState {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Encodes the pixels supplied by the `pixels` iterator into the `output` stream like
+/// [`encode`], but using `state` to keep track of the encoder's state. It's reset before
+/// the image is encoded, so this produces the same data as [`encode`]. Returns the size of
+/// the encoded data.
+///
+/// The image is encoded into the state's buffer first, and then written to `output` all
+/// at once, so unbuffered streams like files only get one write per image.
+pub fn encode_with_state<I, W>(
+    width: NonZeroUsize,
+    height: NonZeroUsize,
+    pixels: I,
+    mut output: W,
+    state: &mut EncoderState,
+) -> Result<usize, Error>
+where
+    I: IntoIterator<Item = Pixel>,
+    W: Write,
+{
+    let num_pixels = num_pixels(width, height)?;
+    state.reset();
+
+    let buf = &mut state.buf;
+    let mut write = |bytes: &[u8]| {
+        buf.extend_from_slice(bytes);
+        Ok(())
+    };
+    write(&header(width.get(), height.get(), Channels::Rgba, 0))?;
+    let mut pixels = pixels.into_iter();
+    encode_chunks(&mut pixels, num_pixels, &mut state.chunks, &mut write)?;
+    write(&END_MARKER)?;
+
+    output.write_all(&state.buf)?;
+    Ok(state.buf.len())
+}
+
+/// Encodes an image into the `output` stream on multiple threads, by splitting it into
+/// horizontal stripes of `stripe_rows` rows (or 1, if it's 0) and encoding each of them
+/// at the same time. `pixels` must have at least `width * height` pixels. The image is
+/// marked as having 4 channels. Returns the size of the encoded data.
+///
+/// The result is a standard file that decodes to the same pixels as one from [`encode`],
+/// but each stripe is encoded without knowing which colors came before it, so it's a
+/// little bigger. A run can't continue from one stripe into the next, and the first time
+/// each color shows up in a stripe, it can't be stored as a 1-byte index. This adds at
+/// most a few hundred bytes per stripe, so tall stripes keep the overhead small.
+#[cfg(feature = "rayon")]
+pub fn encode_parallel<W>(
+    width: NonZeroUsize,
+    height: NonZeroUsize,
+    pixels: &[Pixel],
+    mut output: W,
+    stripe_rows: usize,
+) -> Result<usize, Error>
+where
+    W: Write,
+{
+    use rayon::prelude::*;
+
+    let needed = num_pixels(width, height)?;
+    let pixels = pixels.get(..needed).ok_or(Error::BufferTooSmall {
+        needed,
+        got: pixels.len(),
+    })?;
+
+    // Encode each stripe's chunks into its own buffer
+    let stripe_len = width.get() * stripe_rows.max(1);
+    let stripes = pixels
+        .par_chunks(stripe_len)
+        .enumerate()
+        .map(|(i, stripe)| {
+            // The decoder's state carries over from the previous stripe, but we only
+            // know its previous pixel, so start from a lookup table with no colors in it
+            let mut chunks = match i {
+                0 => ChunkEncoder::new([Pixel::transparent(); 64]),
+                _ => ChunkEncoder {
+                    prev: pixels[i * stripe_len - 1],
+                    ..ChunkEncoder::new(ChunkEncoder::EMPTY_LOOKUP)
+                },
+            };
+            let mut data = Vec::new();
+            let mut write = |buf: &[u8]| {
+                data.extend_from_slice(buf);
+                Ok(())
+            };
+            encode_chunks(
+                &mut stripe.iter().copied(),
+                stripe.len(),
+                &mut chunks,
+                &mut write,
+            )?;
+            Ok(data)
+        })
+        .collect::<Result<Vec<Vec<u8>>, Error>>()?;
+
+    // Join them all up into one image
+    output.write_all(&header(width.get(), height.get(), Channels::Rgba, 0))?;
+    let mut num_bytes = HEADER_SIZE;
+    for data in &stripes {
+        output.write_all(data)?;
+        num_bytes += data.len();
+    }
+    output.write_all(&END_MARKER)?;
+    Ok(num_bytes + END_MARKER.len())
+}
+
+/// Count how many of the RGBA pixels at the start of `data` have the same `bytes`.
+#[inline]
+fn run_length(data: &[u8], bytes: [u8; 4]) -> usize {
+    // Compare 4 pixels at a time, until we find a group that aren't all the same
+    #[cfg(feature = "simd")]
+    let n = {
+        let lanes: wide::u8x16 = bytemuck::cast([bytes; 4]);
+        let same = data.chunks_exact(16).take_while(|chunk| {
+            let chunk: wide::u8x16 = bytemuck::pod_read_unaligned(chunk);
+            chunk.cmp_eq(lanes).all()
+        });
+        same.count() * 4
+    };
+    #[cfg(not(feature = "simd"))]
+    let n = 0;
+
+    // Then find exactly where in the group (or the rest of the data) the run ends
+    n + data[n * 4..]
+        .chunks_exact(4)
+        .take_while(|c| *c == bytes)
+        .count()
+}
+
+/// Get the bytes of an image with `channels` interleaved bytes per pixel, making sure
+/// there are enough of them.
+fn interleaved(
+    width: NonZeroUsize,
+    height: NonZeroUsize,
+    data: &[u8],
+    channels: usize,
+) -> Result<&[u8], Error> {
+    let needed = num_pixels(width, height)?.saturating_mul(channels);
+    data.get(..needed).ok_or(Error::BufferTooSmall {
+        needed,
+        got: data.len(),
+    })
+}
+
+fn encode_impl<I, W>(
+    width: NonZeroUsize,
+    height: NonZeroUsize,
+    pixels: I,
+    output: W,
+    options: EncodeOptions,
+) -> Result<usize, Error>
+where
+    I: Iterator<Item = Pixel>,
+    W: Write,
+{
+    // Keep supplying the padding pixel after the iterator runs out, so it never does
+    match options.on_short {
+        ShortPolicy::Error => encode_exact(width, height, pixels, output, options),
+        ShortPolicy::Pad(px) => {
+            let pixels = pixels.chain(core::iter::repeat(px));
+            encode_exact(width, height, pixels, output, options)
+        }
+    }
+}
+
+/// Encode the image, failing if the iterator doesn't supply all of its pixels.
+fn encode_exact<I, W>(
+    width: NonZeroUsize,
+    height: NonZeroUsize,
+    pixels: I,
+    mut output: W,
+    options: EncodeOptions,
+) -> Result<usize, Error>
+where
+    I: Iterator<Item = Pixel>,
+    W: Write,
+{
+    num_pixels(width, height)?;
+
+    // Gather up the whole image, and encode it again with its rows in reverse
+    if options.flip_vertically {
+        let (w, h) = (width.get(), height.get());
+        let image: Vec<Pixel> = pixels.take(w * h).collect();
+        if image.len() < w * h {
+            return Err(Error::IteratorEmpty {
+                expected: w * h,
+                got: image.len(),
+            });
+        }
+        let pixels = image.chunks_exact(w).rev().flatten().copied();
+        let options = EncodeOptions {
+            flip_vertically: false,
+            ..options
+        };
+        return encode_exact(width, height, pixels, output, options);
+    }
+
+    // RGB images can't store alpha, so make sure every pixel is opaque
+    let opaque = options.channels == Channels::Rgb;
+    let mut pixels = pixels.map(|px| if opaque { Pixel { a: 255, ..px } } else { px });
+
+    if !options.reorder_for_runs {
+        return encode_ordered(width, height, pixels, output, options, 0);
+    }
+
+    // Gather up the whole image so we can encode it in both orders
+    let (w, h) = (width.get(), height.get());
+    let image: Vec<Pixel> = pixels.by_ref().take(w * h).collect();
+    if image.len() < w * h {
+        return Err(Error::IteratorEmpty {
+            expected: w * h,
+            got: image.len(),
+        });
+    }
+    let serpentine = (0..w * h).map(|i| {
+        let (x, y) = (i % w, i / w);
+        image[y * w + if y % 2 == 1 { w - 1 - x } else { x }]
+    });
+
+    let mut row_major = Vec::new();
+    let mut reordered = Vec::new();
+    let pixels = image.iter().copied();
+    encode_ordered(width, height, pixels, &mut row_major, options, 0)?;
+    encode_ordered(
+        width,
+        height,
+        serpentine,
+        &mut reordered,
+        options,
+        SERPENTINE_FLAG,
+    )?;
+
+    let smallest = if reordered.len() < row_major.len() {
+        reordered
+    } else {
+        row_major
+    };
+    output.write_all(&smallest)?;
+    Ok(smallest.len())
+}
+
+/// Encode the pixels in the order they are supplied, marking the header with `flags`.
+fn encode_ordered<I, W>(
     width: NonZeroUsize,
     height: NonZeroUsize,
     mut pixels: I,
     mut output: W,
+    options: EncodeOptions,
+    flags: u8,
 ) -> Result<usize, Error>
 where
     I: Iterator<Item = Pixel>,
@@ -25,23 +811,244 @@ where
     };
 
     // Write the file header
-    write(&MAGIC.to_be_bytes())?;
-    write(&(width as u32).to_be_bytes())?;
-    write(&(height as u32).to_be_bytes())?;
-    write(&[4, 0])?;
+    write(&header(
+        width,
+        height,
+        options.channels,
+        u8::from(options.colorspace) | flags,
+    ))?;
 
     // A running lookup table of previously seen pixels
-    let mut lookup = [Pixel::transparent(); 64];
+    let mut lookup = options.seed_lookup.unwrap_or([Pixel::transparent(); 64]);
+    let num_pixels = width * height;
+
+    // Encode the pixels, and mark the end of the data block
+    if options.legacy {
+        encode_legacy(&mut pixels, num_pixels, &mut lookup, &mut write)?;
+        write(&LEGACY_END_MARKER)?;
+    } else {
+        let mut chunks = ChunkEncoder::new(lookup);
+        encode_chunks(&mut pixels, num_pixels, &mut chunks, &mut write)?;
+        write(&END_MARKER)?;
+    }
+
+    // Return the total amount of bytes that were encoded
+    Ok(num_bytes)
+}
+
+/// Get the amount of pixels in an image, making sure its size can be stored in a header.
+fn num_pixels(width: NonZeroUsize, height: NonZeroUsize) -> Result<usize, Error> {
+    let (width, height) = (width.get(), height.get());
+    if u32::try_from(width).is_err() || u32::try_from(height).is_err() {
+        return Err(Error::ImageTooLarge { width, height });
+    }
+    width
+        .checked_mul(height)
+        .ok_or(Error::ImageTooLarge { width, height })
+}
+
+/// Build the file header for an image.
+fn header(width: usize, height: usize, channels: Channels, colorspace: u8) -> [u8; HEADER_SIZE] {
+    let mut header = [0; HEADER_SIZE];
+    header[..4].copy_from_slice(&MAGIC.to_be_bytes());
+    header[4..8].copy_from_slice(&(width as u32).to_be_bytes());
+    header[8..12].copy_from_slice(&(height as u32).to_be_bytes());
+    header[12] = channels.into();
+    header[13] = colorspace;
+    header
+}
+
+/// Encode `num_pixels` pixels as chunks of the released QOI specification.
+fn encode_chunks<I, F>(
+    pixels: &mut I,
+    num_pixels: usize,
+    chunks: &mut ChunkEncoder,
+    write: &mut F,
+) -> Result<(), Error>
+where
+    I: Iterator<Item = Pixel>,
+    F: FnMut(&[u8]) -> io::Result<()>,
+{
+    let mut count = 0;
+    while count < num_pixels {
+        // Get our next pixel, returning an error if the iterator runs dry
+        let px = pixels.next().ok_or(Error::IteratorEmpty {
+            expected: num_pixels,
+            got: count,
+        })?;
+        count += 1;
+        if px != chunks.prev {
+            chunks.push(px, count == num_pixels, write)?;
+            continue;
+        }
+
+        // Count the rest of the run in a tight loop, so large areas of the same color
+        // don't go through all the checks a new color needs
+        let mut run = 1;
+        let mut end = None;
+        while count < num_pixels {
+            let px = pixels.next().ok_or(Error::IteratorEmpty {
+                expected: num_pixels,
+                got: count,
+            })?;
+            count += 1;
+            if px != chunks.prev {
+                end = Some(px);
+                break;
+            }
+            run += 1;
+        }
+        chunks.push_run(run, end.is_none(), write)?;
+        if let Some(px) = end {
+            chunks.push(px, count == num_pixels, write)?;
+        }
+    }
+    Ok(())
+}
+
+/// The state carried from one pixel to the next while encoding chunks of the released
+/// QOI specification.
+#[derive(Clone, Debug)]
+struct ChunkEncoder {
+    lookup: [Pixel; 64],
+    prev: Pixel,
+    run: u8,
+}
+
+impl ChunkEncoder {
+    /// A lookup table that no pixel will ever be found in, because each slot holds a
+    /// pixel whose index is a different one. Opaque black's index is 53.
+    #[cfg(feature = "rayon")]
+    const EMPTY_LOOKUP: [Pixel; 64] = {
+        let mut lookup = [Pixel::transparent(); 64];
+        lookup[0] = Pixel::rgba(0, 0, 0, 255);
+        lookup
+    };
+
+    #[inline]
+    fn new(lookup: [Pixel; 64]) -> Self {
+        Self {
+            lookup,
+            prev: Pixel::rgba(0, 0, 0, 255),
+            run: 0,
+        }
+    }
+
+    /// Encode the next pixel, where `last` says if it's the last pixel of the image.
+    #[inline]
+    fn push<F>(&mut self, px: Pixel, last: bool, write: &mut F) -> io::Result<()>
+    where
+        F: FnMut(&[u8]) -> io::Result<()>,
+    {
+        let prev = self.prev;
+        let lookup = &mut self.lookup;
+        if px == prev {
+            // If multiple pixels are same in a row, increase the run-length, and
+            // write it out if it's as long as a run can be or we're out of pixels
+            self.run += 1;
+            if self.run == MAX_RUN || last {
+                write(&[OP_RUN | (self.run - 1)])?;
+                self.run = 0;
+            }
+        } else {
+            // If we had a run going, this pixel is the end of it
+            if self.run > 0 {
+                write(&[OP_RUN | (self.run - 1)])?;
+                self.run = 0;
+            }
+
+            let index_u8 = px.index();
+            let index = index_u8 as usize;
+            if lookup[index] == px {
+                // If our pixel is in the lookup table, we can just write an
+                // index byte indicating which position in the table it's at
+                write(&[OP_INDEX | index_u8])?;
+            } else {
+                // If the pixel is different than the lookup value, overwrite it
+                lookup[index] = px;
+
+                if px.a == prev.a {
+                    // Get the wrapping difference between this and the previous pixel
+                    let vr = px.r.wrapping_sub(prev.r) as i8;
+                    let vg = px.g.wrapping_sub(prev.g) as i8;
+                    let vb = px.b.wrapping_sub(prev.b) as i8;
+                    let vg_r = vr.wrapping_sub(vg);
+                    let vg_b = vb.wrapping_sub(vg);
+
+                    if (-2..2).contains(&vr) && (-2..2).contains(&vg) && (-2..2).contains(&vb) {
+                        // If each difference fits in 2 bits, pack them into one byte (DIFF)
+                        write(&[OP_DIFF
+                            | ((vr + 2) as u8) << 4
+                            | ((vg + 2) as u8) << 2
+                            | ((vb + 2) as u8)])?;
+                    } else if (-8..8).contains(&vg_r)
+                        && (-32..32).contains(&vg)
+                        && (-8..8).contains(&vg_b)
+                    {
+                        // If the green difference fits in 6 bits, and the red and blue
+                        // differences relative to it fit in 4 bits, use two bytes (LUMA)
+                        write(&[
+                            OP_LUMA | ((vg + 32) as u8),
+                            ((vg_r + 8) as u8) << 4 | ((vg_b + 8) as u8),
+                        ])?;
+                    } else {
+                        // Otherwise store the color values, keeping the alpha (RGB)
+                        write(&[OP_RGB, px.r, px.g, px.b])?;
+                    }
+                } else {
+                    // The alpha changed, so store the whole pixel (RGBA)
+                    write(&[OP_RGBA, px.r, px.g, px.b, px.a])?;
+                }
+            }
+        }
+
+        self.prev = px;
+        Ok(())
+    }
+
+    /// Encode `n` more copies of the previous pixel, where `last` says if they're the
+    /// last pixels of the image. This writes the same chunks as pushing them one by one.
+    #[inline]
+    fn push_run<F>(&mut self, n: usize, last: bool, write: &mut F) -> io::Result<()>
+    where
+        F: FnMut(&[u8]) -> io::Result<()>,
+    {
+        let total = self.run as usize + n;
+        for _ in 0..total / MAX_RUN as usize {
+            write(&[OP_RUN | (MAX_RUN - 1)])?;
+        }
+        self.run = (total % MAX_RUN as usize) as u8;
+        if last && self.run > 0 {
+            write(&[OP_RUN | (self.run - 1)])?;
+            self.run = 0;
+        }
+        Ok(())
+    }
+}
+
+/// Encode `num_pixels` pixels as chunks of the pre-release draft format.
+fn encode_legacy<I, F>(
+    pixels: &mut I,
+    num_pixels: usize,
+    lookup: &mut [Pixel; 64],
+    write: &mut F,
+) -> Result<(), Error>
+where
+    I: Iterator<Item = Pixel>,
+    F: FnMut(&[u8]) -> io::Result<()>,
+{
     let mut prev = Pixel::rgba(0, 0, 0, 255);
     let mut run: u16 = 0;
-    let num_pixels = width * height;
     let mut count = 0;
 
     while count < num_pixels {
         count += 1;
 
         // Get our next pixel, returning an error if the iterator runs dry
-        let px = pixels.next().ok_or_else(|| Error::IteratorEmpty)?;
+        let px = pixels.next().ok_or(Error::IteratorEmpty {
+            expected: num_pixels,
+            got: count - 1,
+        })?;
 
         // If multiple pixels are same in a row, increase the run-length
         if px == prev {
@@ -49,7 +1056,7 @@ where
         }
 
         // Check if we've got a run going, but we've hit the end of it
-        if run > 0 && (run == 0x2020 || px != prev || count == num_pixels) {
+        if run > 0 && (run == LEGACY_MAX_RUN || px != prev || count == num_pixels) {
             if run < 33 {
                 // If it's a short run, encode it in 1 byte (RUN_8)
                 run -= 1;
@@ -64,7 +1071,7 @@ where
 
         // If this pixel isn't a run
         if px != prev {
-            let index_u8 = px.hash() % 64;
+            let index_u8 = px.legacy_hash() % 64;
             let index = index_u8 as usize;
             if lookup[index] == px {
                 // If our pixel is in the lookup table, we can just write an
@@ -154,9 +1161,291 @@ where
         prev = px;
     }
 
-    // Mark the end of the data block with 4 empty bytes
-    write(&[0, 0, 0, 0])?;
+    Ok(())
+}
 
-    // Return the total amount of bytes that were encoded
-    Ok(num_bytes)
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{SPEC_IMAGE, SPEC_PIXELS};
+
+    fn size(n: usize) -> NonZeroUsize {
+        NonZeroUsize::new(n).unwrap()
+    }
+
+    #[cfg(target_pointer_width = "64")]
+    #[test]
+    fn encode_to_vec_checks_the_size_first() {
+        // Too big for a header
+        let result = encode_to_vec(size(1 << 33), size(1), core::iter::empty());
+        assert!(matches!(result, Err(Error::ImageTooLarge { .. })));
+
+        // Fits in a header, but there's no way to allocate room for it
+        let result = encode_to_vec(size(u32::MAX as usize), size(u32::MAX as usize), []);
+        assert!(matches!(result, Err(Error::ImageTooLarge { .. })));
+    }
+
+    #[test]
+    fn estimates_the_exact_size() {
+        let images: [Vec<Pixel>; 4] = [
+            (0..100).map(|i| Pixel::rgba(i, i % 7, 0, 255)).collect(),
+            [Pixel::gray(40); 100].to_vec(),
+            (0..100)
+                .map(|i: u8| Pixel::rgba(i.wrapping_mul(37), i.wrapping_mul(91), i ^ 0x5a, i * 2))
+                .collect(),
+            (0..100).map(|i| Pixel::gray(i / 30 * 50)).collect(),
+        ];
+        for pixels in images {
+            let data = encode_to_vec(size(10), size(10), pixels.iter().copied()).unwrap();
+            assert_eq!(estimate_encoded_size(&pixels).unwrap(), data.len());
+        }
+        assert_eq!(
+            estimate_encoded_size(&[]).unwrap(),
+            HEADER_SIZE + END_MARKER.len()
+        );
+    }
+
+    #[test]
+    fn encodes_the_spec_fixture() {
+        let data = encode_to_vec(size(4), size(2), SPEC_PIXELS).unwrap();
+        assert_eq!(data, SPEC_IMAGE);
+
+        let rgba: Vec<u8> = SPEC_PIXELS
+            .iter()
+            .flat_map(|&px| <[u8; 4]>::from(px))
+            .collect();
+        let mut data = Vec::new();
+        let len = encode_rgba(size(4), size(2), &rgba, &mut data).unwrap();
+        assert_eq!((len, data), (SPEC_IMAGE.len(), SPEC_IMAGE.to_vec()));
+    }
+
+    #[test]
+    fn reuses_encoder_states() {
+        let mut state = EncoderState::new();
+        let frames = [SPEC_PIXELS, [Pixel::gray(40); 8], SPEC_PIXELS];
+        let mut capacity = 0;
+        for (i, frame) in frames.into_iter().enumerate() {
+            let mut data = Vec::new();
+            let len = encode_with_state(size(4), size(2), frame, &mut data, &mut state).unwrap();
+            assert_eq!(data, encode_to_vec(size(4), size(2), frame).unwrap());
+            assert_eq!(len, data.len());
+
+            // The buffer only grows for the first frame, since none of the others are bigger
+            if i == 0 {
+                capacity = state.buf.capacity();
+            }
+            assert_eq!(state.buf.capacity(), capacity);
+        }
+    }
+
+    #[test]
+    fn seeded_lookups_round_trip() {
+        let palette = [
+            Pixel::rgb(200, 10, 10),
+            Pixel::rgb(10, 200, 10),
+            Pixel::rgb(10, 10, 200),
+        ];
+        let pixels = [palette, palette].concat();
+        let seed_lookup = seed_lookup_table(&palette);
+        let options = EncodeOptions {
+            seed_lookup: Some(seed_lookup),
+            ..EncodeOptions::default()
+        };
+        let mut seeded = Vec::new();
+        encode_with_options(size(6), size(1), pixels.clone(), &mut seeded, options).unwrap();
+        let plain = encode_to_vec(size(6), size(1), pixels.clone()).unwrap();
+
+        // Every pixel is an index into the seeded table, even the first of each color
+        assert_eq!(seeded.len(), HEADER_SIZE + 6 + END_MARKER.len());
+        assert!(seeded.len() < plain.len());
+
+        let decoder = crate::Decoder::new().seed_lookup(seed_lookup);
+        let (_, _, decoded, _) = decoder.decode_to_vec(&seeded[..]).unwrap();
+        assert_eq!(decoded, pixels);
+    }
+
+    /// Encode `n` copies of `px` in a row with both [`encode`] and [`encode_rgba`], making sure
+    /// they agree and decode back to the same pixels, and return the data block.
+    fn encode_run(px: Pixel, n: usize) -> Vec<u8> {
+        let mut data = Vec::new();
+        encode(size(n), size(1), core::iter::repeat_n(px, n), &mut data).unwrap();
+        let rgba = [<[u8; 4]>::from(px)].repeat(n).concat();
+        let mut from_rgba = Vec::new();
+        encode_rgba(size(n), size(1), &rgba, &mut from_rgba).unwrap();
+        assert_eq!(data, from_rgba, "{} pixels", n);
+
+        let (_, _, decoded, _) = crate::Decoder::new().decode_to_vec(&data[..]).unwrap();
+        assert_eq!(decoded, [px].repeat(n), "{} pixels", n);
+        data[HEADER_SIZE..data.len() - END_MARKER.len()].to_vec()
+    }
+
+    #[test]
+    fn splits_runs_at_the_max_length() {
+        let max = MAX_RUN as usize;
+        let full = OP_RUN | (MAX_RUN - 1);
+
+        // These start a run straight away, since they match the starting pixel
+        let black = Pixel::rgba(0, 0, 0, 255);
+        assert_eq!(encode_run(black, max - 1), [OP_RUN | (MAX_RUN - 2)]);
+        assert_eq!(encode_run(black, max), [full]);
+        assert_eq!(encode_run(black, max + 1), [full, OP_RUN]);
+        assert_eq!(encode_run(black, max * 2), [full, full]);
+        assert_eq!(encode_run(black, max * 2 + 1), [full, full, OP_RUN]);
+        assert_eq!(encode_run(black, 130), [full, full, OP_RUN | 5]);
+
+        // These have to store the first pixel before repeating it
+        let px = Pixel::rgba(10, 200, 30, 255);
+        for n in [max, max + 1, max + 2, max * 2 + 1, 130] {
+            let block = encode_run(px, n);
+            assert_eq!(block[..4], [OP_RGB, 10, 200, 30]);
+            let runs: usize = block[4..].iter().map(|b| (b - OP_RUN) as usize + 1).sum();
+            assert_eq!(runs, n - 1);
+            assert!(block[4..block.len() - 1].iter().all(|&b| b == full));
+        }
+    }
+
+    /// Runs of every length around the 16-byte groups [`run_length`] compares at once, so
+    /// both the vectorized and scalar scans are checked wherever a run can end.
+    #[test]
+    fn run_length_matches_a_scalar_scan() {
+        let bytes = [1, 2, 3, 4];
+        for len in 0..40 {
+            for end in 0..=len {
+                let mut data = [bytes].repeat(len).concat();
+                if end < len {
+                    data[end * 4 + 3] = 5;
+                }
+                let scalar = data.chunks_exact(4).take_while(|c| *c == bytes).count();
+                assert_eq!(scalar, end);
+                assert_eq!(run_length(&data, bytes), scalar, "{} of {}", end, len);
+            }
+        }
+    }
+
+    #[test]
+    fn max_encoded_size_doesnt_overflow() {
+        assert_eq!(
+            max_encoded_size(size(3), size(2), Channels::Rgba),
+            Some(HEADER_SIZE + 30 + END_MARKER.len())
+        );
+        assert_eq!(
+            max_encoded_size(size(3), size(2), Channels::Rgb),
+            Some(HEADER_SIZE + 24 + END_MARKER.len())
+        );
+        assert_eq!(
+            max_encoded_size(size(usize::MAX), size(2), Channels::Rgba),
+            None
+        );
+        assert_eq!(
+            max_encoded_size(size(usize::MAX / 5), size(1), Channels::Rgba),
+            None
+        );
+    }
+
+    #[test]
+    fn planes_round_trip() {
+        let pixels: Vec<Pixel> = (0..12)
+            .map(|i| Pixel::rgba(i * 20, 7, 255 - i, i * 3))
+            .collect();
+        let planes: [Vec<u8>; 4] =
+            core::array::from_fn(|c| pixels.iter().map(|&px| <[u8; 4]>::from(px)[c]).collect());
+        let [r, g, b, a] = &planes;
+
+        let mut data = Vec::new();
+        encode_planar(size(4), size(3), r, g, b, Some(a), &mut data).unwrap();
+        assert_eq!(
+            crate::decode_planar(&data[..]).unwrap(),
+            (4, 3, planes.clone())
+        );
+
+        // Without an alpha plane, the image is opaque and has 3 channels
+        let mut data = Vec::new();
+        encode_planar(size(4), size(3), r, g, b, None, &mut data).unwrap();
+        let (header, _) = crate::decode_slice(&data).unwrap();
+        assert_eq!(header.channels, Channels::Rgb);
+        let (_, _, [_, _, _, a]) = crate::decode_planar(&data[..]).unwrap();
+        assert_eq!(a, [255; 12]);
+
+        assert!(matches!(
+            encode_planar(size(4), size(3), r, &g[1..], b, None, Vec::new()),
+            Err(Error::BufferTooSmall {
+                needed: 12,
+                got: 11
+            })
+        ));
+    }
+
+    #[test]
+    fn cancels_at_the_next_row() {
+        let pixels: Vec<_> = (0..100).map(|i| Pixel::gray(i as u8)).collect();
+        let (checks, taken) = (Cell::new(0), Cell::new(0));
+        let result = encode_cancellable(
+            size(10),
+            size(10),
+            pixels
+                .iter()
+                .inspect(|_| taken.set(taken.get() + 1))
+                .copied(),
+            Vec::new(),
+            || {
+                checks.set(checks.get() + 1);
+                checks.get() > 2
+            },
+        );
+        assert!(matches!(result, Err(Error::Cancelled)));
+        assert_eq!((checks.get(), taken.get()), (3, 21));
+
+        let mut data = Vec::new();
+        encode_cancellable(size(10), size(10), pixels.clone(), &mut data, || false).unwrap();
+        assert_eq!(data, encode_to_vec(size(10), size(10), pixels).unwrap());
+    }
+
+    #[test]
+    fn strips_round_trip() {
+        let gradient: Vec<_> = (0..=255)
+            .map(|v| Pixel::rgba(v, 255 - v, v / 2, 255))
+            .collect();
+        let mut data = Vec::new();
+        let len = encode_strip(&gradient, &mut data).unwrap();
+        assert_eq!(len, data.len());
+        assert_eq!(crate::decode_strip(&data[..]).unwrap(), gradient);
+
+        assert!(matches!(
+            encode_strip(&[], Vec::new()),
+            Err(Error::NoImageSize)
+        ));
+        let square = encode_to_vec(size(16), size(16), gradient).unwrap();
+        assert!(matches!(
+            crate::decode_strip(&square[..]),
+            Err(Error::DimensionMismatch {
+                expected: (16, 1),
+                found: (16, 16)
+            })
+        ));
+    }
+
+    #[test]
+    fn serpentine_order_joins_runs_across_rows() {
+        // Rows that are half black and half white, so runs end at each side of the image
+        let pixels: Vec<_> = (0..64 * 16)
+            .map(|i| Pixel::gray(if i % 64 < 32 { 0 } else { 255 }))
+            .collect();
+        let encode = |reorder_for_runs| {
+            let options = EncodeOptions {
+                reorder_for_runs,
+                ..EncodeOptions::default()
+            };
+            let mut data = Vec::new();
+            encode_with_options(size(64), size(16), pixels.clone(), &mut data, options).unwrap();
+            data
+        };
+        let (plain, serpentine) = (encode(false), encode(true));
+        assert!(serpentine.len() < plain.len());
+        assert_eq!(serpentine[13] & SERPENTINE_FLAG, SERPENTINE_FLAG);
+
+        let (_, _, decoded, _) = crate::Decoder::new()
+            .decode_to_vec(&serpentine[..])
+            .unwrap();
+        assert_eq!(decoded, pixels);
+    }
 }
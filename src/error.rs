@@ -1,13 +1,18 @@
-use std::fmt::{Debug, Display, Formatter};
+use crate::io;
+use alloc::string::String;
+use core::fmt::{Display, Formatter};
 
 /// An encoding/decoding error.
 #[derive(Debug)]
 pub enum Error {
     /// There was a read/write error.
-    Io(std::io::Error),
+    Io(io::Error),
 
-    /// You tried to draw from an empty iterator.
-    IteratorEmpty,
+    /// The stream ended partway through the image, `offset` bytes into it.
+    UnexpectedEof { offset: usize },
+
+    /// The iterator of pixels being encoded ran out after `got` of the `expected` pixels.
+    IteratorEmpty { expected: usize, got: usize },
 
     /// The decoding file didn't begin with `qoif`.
     InvalidFileTypeMarker([u8; 4]),
@@ -17,20 +22,279 @@ pub enum Error {
 
     /// The data block of your image has no bytes
     NoImageData,
+
+    /// The image's size, as `(width, height)`, didn't match what was expected.
+    DimensionMismatch {
+        expected: (usize, usize),
+        found: (usize, usize),
+    },
+
+    /// The file looks like it's in a different version of the format than the one being
+    /// decoded: either the released QOI specification, or the pre-release draft format
+    /// (see [`Decoder::legacy`](crate::Decoder::legacy)). They use different opcodes, so
+    /// decoding it as the wrong one would only produce garbage.
+    UnsupportedQoiVersion,
+
+    /// The file extension, if any, of a path being saved to isn't a supported format.
+    UnsupportedExtension(String),
+
+    /// A string couldn't be parsed as a hex color by
+    /// [`Pixel::from_hex`](crate::Pixel::from_hex).
+    InvalidHex(String),
+
+    /// An image that declared 3 channels contained pixels that weren't fully opaque.
+    AlphaInRgbImage,
+
+    /// The image has too many pixels to count in a `usize`, or is too big to fit its size
+    /// in a file's header.
+    ImageTooLarge { width: usize, height: usize },
+
+    /// A buffer didn't have enough values in it for the size of the image.
+    BufferTooSmall { needed: usize, got: usize },
+
+    /// A buffer had `got` values in it, when exactly `expected` were needed.
+    LengthMismatch { expected: usize, got: usize },
+
+    /// The operation was cancelled by the caller before it finished.
+    Cancelled,
+
+    /// An image's header had a channel count other than 3 or 4.
+    InvalidChannels(u8),
+
+    /// An image's header had a colorspace other than 0 (sRGB) or 1 (linear).
+    InvalidColorspace(u8),
+
+    /// The data block has a chunk that would produce more pixels than the image's size,
+    /// or more pixels were pushed into an [`Encoder`](crate::Encoder) than fit in it.
+    TooMuchData,
+
+    /// The image ended before all of its pixels were there, so only `got` of the
+    /// `expected` pixels were decoded from the file or pushed into an
+    /// [`Encoder`](crate::Encoder). If `got` is `expected`, the file ended partway
+    /// through its end marker.
+    Truncated { expected: usize, got: usize },
+
+    /// The image's data block wasn't followed by its end marker. This is only checked
+    /// for by [`Pixels::verify_end_marker`](crate::Pixels::verify_end_marker).
+    MissingEndMarker,
+
+    /// A snapshot given to [`Pixels::restore_state`](crate::Pixels::restore_state) was
+    /// taken from a decoder for an image in a different format. Snapshots from an image of
+    /// a different size are reported as [`Error::DimensionMismatch`] instead.
+    StateMismatch,
+}
+
+/// A coarse category of [`Error`], for handling broad classes of errors
+/// without matching every variant.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum ErrorKind {
+    /// A read/write error from the underlying stream, or the caller stopping the
+    /// operation partway through. Either way, a stream being written to may have been left
+    /// with only part of an image in it.
+    Io,
+
+    /// The input isn't a validly structured QOI file.
+    Format,
+
+    /// An image or buffer had the wrong size.
+    Size,
+
+    /// The pixel data was missing or couldn't be used.
+    Data,
 }
 
-impl From<std::io::Error> for Error {
+impl Error {
+    /// Get the category this error falls into.
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Error::Io(_) | Error::Cancelled => ErrorKind::Io,
+            Error::InvalidFileTypeMarker(_)
+            | Error::UnsupportedQoiVersion
+            | Error::UnsupportedExtension(_)
+            | Error::AlphaInRgbImage
+            | Error::InvalidChannels(_)
+            | Error::InvalidColorspace(_)
+            | Error::MissingEndMarker => ErrorKind::Format,
+            Error::NoImageSize
+            | Error::DimensionMismatch { .. }
+            | Error::ImageTooLarge { .. }
+            | Error::BufferTooSmall { .. }
+            | Error::LengthMismatch { .. } => ErrorKind::Size,
+            Error::IteratorEmpty { .. }
+            | Error::UnexpectedEof { .. }
+            | Error::NoImageData
+            | Error::TooMuchData
+            | Error::InvalidHex(_)
+            | Error::Truncated { .. }
+            | Error::StateMismatch => ErrorKind::Data,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
     #[inline]
-    fn from(err: std::io::Error) -> Self {
+    fn from(err: io::Error) -> Self {
         Error::Io(err)
     }
 }
 
 impl Display for Error {
-    #[inline]
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        Debug::fmt(self, f)
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Error::Io(err) => write!(f, "I/O error: {}", err),
+            Error::UnexpectedEof { offset } => {
+                write!(f, "the stream ended unexpectedly at byte {}", offset)
+            }
+            Error::IteratorEmpty { expected, got } => write!(
+                f,
+                "ran out of pixels before the image was complete: expected {} pixels, but got {}",
+                expected, got
+            ),
+            Error::InvalidFileTypeMarker(magic) => match other_format(magic) {
+                Some(format) => write!(
+                    f,
+                    "invalid QOI magic bytes: expected 'qoif', found what looks like a {}",
+                    format
+                ),
+                None => write!(f, "invalid QOI magic bytes: {:?}", magic),
+            },
+            Error::NoImageSize => write!(f, "the image has no size"),
+            Error::NoImageData => write!(f, "the image has no data"),
+            Error::DimensionMismatch { expected, found } => write!(
+                f,
+                "expected a {}x{} image, but found a {}x{} one",
+                expected.0, expected.1, found.0, found.1
+            ),
+            Error::UnsupportedQoiVersion => {
+                write!(f, "the file is in a different version of the QOI format")
+            }
+            Error::UnsupportedExtension(ext) => {
+                write!(f, "unsupported file extension: {:?}", ext)
+            }
+            Error::InvalidHex(s) => write!(f, "invalid hex color: {:?}", s),
+            Error::AlphaInRgbImage => write!(f, "an RGB image contains transparent pixels"),
+            Error::ImageTooLarge { width, height } => {
+                write!(f, "a {}x{} image is too large", width, height)
+            }
+            Error::BufferTooSmall { needed, got } => write!(
+                f,
+                "the buffer is too small: needed {} values, but got {}",
+                needed, got
+            ),
+            Error::LengthMismatch { expected, got } => write!(
+                f,
+                "the buffer has the wrong length: expected {} values, but got {}",
+                expected, got
+            ),
+            Error::Cancelled => write!(f, "the operation was cancelled"),
+            Error::InvalidChannels(channels) => {
+                write!(f, "invalid channel count {}, expected 3 or 4", channels)
+            }
+            Error::InvalidColorspace(colorspace) => {
+                write!(f, "invalid colorspace {}, expected 0 or 1", colorspace)
+            }
+            Error::TooMuchData => write!(f, "there are more pixels than fit in the image"),
+            Error::Truncated { expected, got } if expected == got => {
+                write!(f, "the image ended partway through its end marker")
+            }
+            Error::Truncated { expected, got } => write!(
+                f,
+                "the image ended early: expected {} pixels, but got {}",
+                expected, got
+            ),
+            Error::MissingEndMarker => write!(f, "the image's end marker is missing"),
+            Error::StateMismatch => {
+                write!(
+                    f,
+                    "the decoder's state was saved from a different kind of image"
+                )
+            }
+        }
     }
 }
 
-impl std::error::Error for Error {}
+/// Recognize the magic bytes of other common image formats, so that a file in one
+/// of them can be reported as such instead of as a broken QOI file.
+fn other_format(magic: &[u8; 4]) -> Option<&'static str> {
+    match magic {
+        [0x89, b'P', b'N', b'G'] => Some("PNG"),
+        [0xff, 0xd8, ..] => Some("JPEG"),
+        [b'G', b'I', b'F', b'8'] => Some("GIF"),
+        _ => None,
+    }
+}
+
+impl core::error::Error for Error {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        match self {
+            Error::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_variant_has_a_kind() {
+        let kinds = [
+            (Error::Io(io::ErrorKind::Other.into()), ErrorKind::Io),
+            (Error::Cancelled, ErrorKind::Io),
+            (Error::InvalidFileTypeMarker(*b"qoi "), ErrorKind::Format),
+            (Error::UnsupportedQoiVersion, ErrorKind::Format),
+            (Error::UnsupportedExtension("bmp".into()), ErrorKind::Format),
+            (Error::AlphaInRgbImage, ErrorKind::Format),
+            (Error::InvalidChannels(2), ErrorKind::Format),
+            (Error::InvalidColorspace(2), ErrorKind::Format),
+            (Error::MissingEndMarker, ErrorKind::Format),
+            (Error::NoImageSize, ErrorKind::Size),
+            (
+                Error::DimensionMismatch {
+                    expected: (1, 2),
+                    found: (2, 1),
+                },
+                ErrorKind::Size,
+            ),
+            (
+                Error::ImageTooLarge {
+                    width: usize::MAX,
+                    height: 2,
+                },
+                ErrorKind::Size,
+            ),
+            (Error::BufferTooSmall { needed: 4, got: 3 }, ErrorKind::Size),
+            (
+                Error::LengthMismatch {
+                    expected: 4,
+                    got: 5,
+                },
+                ErrorKind::Size,
+            ),
+            (
+                Error::IteratorEmpty {
+                    expected: 4,
+                    got: 3,
+                },
+                ErrorKind::Data,
+            ),
+            (Error::NoImageData, ErrorKind::Data),
+            (Error::TooMuchData, ErrorKind::Data),
+            (Error::InvalidHex("#ggg".into()), ErrorKind::Data),
+            (Error::StateMismatch, ErrorKind::Data),
+            // A stream that ends early is the same problem whichever part of it was missing
+            (Error::UnexpectedEof { offset: 10 }, ErrorKind::Data),
+            (
+                Error::Truncated {
+                    expected: 4,
+                    got: 3,
+                },
+                ErrorKind::Data,
+            ),
+        ];
+        for (err, kind) in kinds {
+            assert_eq!(err.kind(), kind, "{}", err);
+        }
+    }
+}
@@ -0,0 +1,195 @@
+use crate::{decode, encode, Downsampler, Error, Image, Pixel};
+use std::io::{ErrorKind, Read, Seek, SeekFrom, Write};
+use std::num::NonZeroUsize;
+
+// These are crate-specific extensions to the QOI format, which store extra data after
+// the image's end marker. Standard QOI decoders stop reading at the end marker, so files
+// using them still open as normal images.
+
+/// Marks the end of a file with an embedded thumbnail.
+const THUMBNAIL_TAG: [u8; 4] = *b"qoit";
+
+/// Marks the start of an embedded ICC color profile.
+const ICC_TAG: [u8; 4] = *b"qoic";
+
+/// Encodes the image like [`encode`], then appends the ICC color profile `icc` after the
+/// image's end marker, so it can be read back with [`decode_icc`]. Returns the size of
+/// the encoded data.
+///
+/// The profile is stored after the tag `qoic` and its size in bytes (as a big-endian
+/// `u32`). Standard QOI readers ignore it, so they'll display the image without color
+/// management:
+///
+/// ```text
+/// [image][b"qoic"][profile size: u32][profile]
+/// ```
+pub fn encode_with_icc<I, W>(
+    width: NonZeroUsize,
+    height: NonZeroUsize,
+    pixels: I,
+    icc: &[u8],
+    mut output: W,
+) -> Result<usize, Error>
+where
+    I: IntoIterator<Item = Pixel>,
+    W: Write,
+{
+    let size = u32::try_from(icc.len())
+        .map_err(|_| std::io::Error::new(ErrorKind::InvalidInput, "ICC profile is too large"))?;
+    let num_bytes = encode(width, height, pixels, &mut output)?;
+    output.write_all(&ICC_TAG)?;
+    output.write_all(&size.to_be_bytes())?;
+    output.write_all(icc)?;
+    Ok(num_bytes + 8 + icc.len())
+}
+
+/// Read the ICC color profile stored in a file written by [`encode_with_icc`]. The image
+/// still has to be decoded to find the end of it, but its pixels are discarded. Returns
+/// `None` if the file doesn't have a color profile.
+pub fn decode_icc<R>(input: R) -> Result<Option<Vec<u8>>, Error>
+where
+    R: Read,
+{
+    let (_, _, mut pixels) = decode(input)?;
+    for p in &mut pixels {
+        p?;
+    }
+
+    // Check for the profile's tag right after the image
+    let mut rest = pixels.into_rest();
+    let mut header = [0; 8];
+    match rest.read_exact(&mut header) {
+        Ok(()) if header[..4] == ICC_TAG => {}
+        Ok(()) => return Ok(None),
+        Err(err) if err.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(err) => return Err(err.into()),
+    }
+
+    // Read the profile without trusting its size for the allocation, in case it's corrupt
+    let size = u32::from_be_bytes([header[4], header[5], header[6], header[7]]) as u64;
+    let mut icc = Vec::new();
+    rest.take(size).read_to_end(&mut icc)?;
+    if (icc.len() as u64) < size {
+        return Err(std::io::Error::from(ErrorKind::UnexpectedEof).into());
+    }
+    Ok(Some(icc))
+}
+
+/// Encodes the image like [`encode`], then appends a thumbnail of it after the image's
+/// end marker. The thumbnail is shrunk so neither of its sides exceed `thumb_max_dim`,
+/// and can be read back with [`decode_thumbnail`]. Returns the size of the encoded data.
+///
+/// The thumbnail is a complete QOI image of its own, stored after the main image. It
+/// is followed by its size in bytes (as a big-endian `u32`) and the tag `qoit`, so that
+/// it can be found by seeking from the end of the file:
+///
+/// ```text
+/// [image][thumbnail][thumbnail size: u32][b"qoit"]
+/// ```
+pub fn encode_with_thumbnail<I, W>(
+    width: NonZeroUsize,
+    height: NonZeroUsize,
+    pixels: I,
+    thumb_max_dim: usize,
+    mut output: W,
+) -> Result<usize, Error>
+where
+    I: IntoIterator<Item = Pixel>,
+    W: Write,
+{
+    // Encode the image, building the thumbnail as we go
+    let mut thumb = Downsampler::new(width.get(), height.get(), thumb_max_dim);
+    let mut num_bytes = encode(
+        width,
+        height,
+        pixels.into_iter().inspect(|&p| thumb.push(p)),
+        &mut output,
+    )?;
+
+    // Encode the thumbnail after it
+    let (tw, th, thumb) = thumb.finish();
+    let mut bytes = Vec::new();
+    encode(
+        NonZeroUsize::new(tw).ok_or(Error::NoImageSize)?,
+        NonZeroUsize::new(th).ok_or(Error::NoImageSize)?,
+        thumb,
+        &mut bytes,
+    )?;
+    output.write_all(&bytes)?;
+    output.write_all(&(bytes.len() as u32).to_be_bytes())?;
+    output.write_all(&THUMBNAIL_TAG)?;
+    num_bytes += bytes.len() + 8;
+
+    Ok(num_bytes)
+}
+
+/// Decode the thumbnail stored in a file written by [`encode_with_thumbnail`], without
+/// decoding the main image. Returns `None` if the file doesn't have a thumbnail.
+pub fn decode_thumbnail<R>(mut input: R) -> Result<Option<Image>, Error>
+where
+    R: Read + Seek,
+{
+    // Check for the thumbnail's size and tag at the end of the file
+    let len = input.seek(SeekFrom::End(0))?;
+    if len < 8 {
+        return Ok(None);
+    }
+    input.seek(SeekFrom::End(-8))?;
+    let mut trailer = [0; 8];
+    input.read_exact(&mut trailer)?;
+    if trailer[4..] != THUMBNAIL_TAG {
+        return Ok(None);
+    }
+
+    // Seek back to the start of the thumbnail and decode it
+    let size = u32::from_be_bytes([trailer[0], trailer[1], trailer[2], trailer[3]]) as u64;
+    if size + 8 > len {
+        return Ok(None);
+    }
+    input.seek(SeekFrom::End(-8 - size as i64))?;
+    Image::decode(input.take(size)).map(Some)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn size(n: usize) -> NonZeroUsize {
+        NonZeroUsize::new(n).unwrap()
+    }
+
+    #[test]
+    fn thumbnails_decode_alongside_the_image() {
+        let pixels: Vec<_> = (0..64).map(|i| Pixel::gray(i as u8 / 8 * 30)).collect();
+        let mut data = Vec::new();
+        let len = encode_with_thumbnail(size(8), size(8), pixels.clone(), 2, &mut data).unwrap();
+        assert_eq!(len, data.len());
+
+        // A 4x downsample of rows that each have their own shade
+        let thumb = decode_thumbnail(Cursor::new(&data)).unwrap().unwrap();
+        assert_eq!((thumb.width(), thumb.height()), (2, 2));
+        assert_eq!(thumb.pixels(), [45, 45, 165, 165].map(Pixel::gray));
+
+        assert_eq!(Image::decode(&data[..]).unwrap().pixels(), pixels);
+        let plain = crate::encode_to_vec(size(8), size(8), pixels).unwrap();
+        assert!(decode_thumbnail(Cursor::new(&plain)).unwrap().is_none());
+    }
+
+    #[test]
+    fn icc_profiles_round_trip() {
+        let icc: Vec<u8> = (0..=255).rev().collect();
+        let pixels = vec![Pixel::rgb(9, 8, 7); 6];
+        let mut data = Vec::new();
+        let len = encode_with_icc(size(3), size(2), pixels.clone(), &icc, &mut data).unwrap();
+        assert_eq!(len, data.len());
+        assert_eq!(decode_icc(&data[..]).unwrap(), Some(icc));
+        assert_eq!(Image::decode(&data[..]).unwrap().pixels(), pixels);
+
+        let plain = crate::encode_to_vec(size(3), size(2), pixels).unwrap();
+        assert_eq!(decode_icc(&plain[..]).unwrap(), None);
+
+        // A profile that's cut short is an error, not a shorter profile
+        assert!(decode_icc(&data[..data.len() - 1]).is_err());
+    }
+}
@@ -0,0 +1,39 @@
+//! Images encoded by hand from the QOI specification, for checking the encoder and decoder
+//! against it byte for byte.
+
+use crate::{consts::*, Pixel};
+
+/// A 4x2 image that uses every opcode of the specification, including differences that wrap
+/// around. The comments give the pixel each chunk produces, and why it was stored that way.
+#[rustfmt::skip]
+pub const SPEC_IMAGE: [u8; 37] = [
+    b'q', b'o', b'i', b'f', 0, 0, 0, 4, 0, 0, 0, 2, 4, 0,
+    // (10, 20, 30, 255): too far from (0, 0, 0, 255) for anything but a full color
+    OP_RGB, 10, 20, 30,
+    // (11, 19, 31, 255): a difference of (1, -1, 1), each biased by 2
+    OP_DIFF | (3 << 4) | (1 << 2) | 3,
+    // (11, 19, 31, 255) twice more: a run of 2, biased by -1
+    OP_RUN | 1,
+    // (20, 25, 30, 255): green is 6 more, and red and blue 3 and -7 more than that, biased
+    // by 32 and 8
+    OP_LUMA | 38, (11 << 4) | 1,
+    // (10, 20, 30, 255): the first pixel, at (10*3 + 20*5 + 30*7 + 255*11) % 64 = 9
+    OP_INDEX | 9,
+    // (255, 0, 30, 128): the alpha changed, so every channel has to be stored
+    OP_RGBA, 255, 0, 30, 128,
+    // (0, 255, 30, 128): a difference of (1, -1, 0) once it wraps around
+    OP_DIFF | (3 << 4) | (1 << 2) | 2,
+    0, 0, 0, 0, 0, 0, 0, 1,
+];
+
+/// The pixels of [`SPEC_IMAGE`].
+pub const SPEC_PIXELS: [Pixel; 8] = [
+    Pixel::rgba(10, 20, 30, 255),
+    Pixel::rgba(11, 19, 31, 255),
+    Pixel::rgba(11, 19, 31, 255),
+    Pixel::rgba(11, 19, 31, 255),
+    Pixel::rgba(20, 25, 30, 255),
+    Pixel::rgba(10, 20, 30, 255),
+    Pixel::rgba(255, 0, 30, 128),
+    Pixel::rgba(0, 255, 30, 128),
+];
@@ -0,0 +1,532 @@
+use crate::io::Read;
+use crate::{decode, initial_capacity, Error, Pixel};
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt::{Debug, Formatter, Write as _};
+#[cfg(feature = "std")]
+use std::{fs::File, io::BufWriter, io::Write, num::NonZeroUsize, path::Path};
+
+/// The sampling filter used when resizing an [`Image`].
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum Filter {
+    /// Use the nearest source pixel. Fast and keeps hard edges, which suits pixel art.
+    Nearest,
+
+    /// Blend the four nearest source pixels. Smoother, which suits photos and UI assets.
+    Bilinear,
+}
+
+/// How alpha is treated when a resize blends pixels together.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum AlphaMode {
+    /// Blend every channel independently. Only use this if the image is fully opaque,
+    /// or its color channels are already premultiplied, because the color of transparent
+    /// pixels (usually black) otherwise bleeds into their neighbors as dark fringes.
+    Straight,
+
+    /// Premultiply the color channels by alpha before blending, and divide it back out
+    /// afterwards, so transparent pixels don't contribute any color. Use this for images
+    /// with straight alpha and transparent areas, like most UI assets and sprites.
+    Premultiplied,
+}
+
+/// A decoded image, holding its size and a row-major buffer of pixels.
+///
+/// Two images are equal if they're the same size and all of their pixels are equal.
+/// The `Debug` output only summarizes the pixels, so it stays short for large images.
+#[derive(Clone, Eq, PartialEq)]
+pub struct Image {
+    width: usize,
+    height: usize,
+    pixels: Vec<Pixel>,
+}
+
+impl Image {
+    /// Create a new image filled with the provided pixel.
+    #[inline]
+    pub fn new(width: usize, height: usize, fill: Pixel) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![fill; width * height],
+        }
+    }
+
+    /// Create an image from a row-major buffer of pixels, which must contain exactly
+    /// `width * height` pixels. Fails with [`Error::LengthMismatch`] otherwise, or with
+    /// [`Error::ImageTooLarge`] if that many pixels can't be counted in a `usize`.
+    pub fn from_raw(width: usize, height: usize, pixels: Vec<Pixel>) -> Result<Self, Error> {
+        let len = width
+            .checked_mul(height)
+            .ok_or(Error::ImageTooLarge { width, height })?;
+        if pixels.len() != len {
+            return Err(Error::LengthMismatch {
+                expected: len,
+                got: pixels.len(),
+            });
+        }
+        Ok(Self {
+            width,
+            height,
+            pixels,
+        })
+    }
+
+    /// Split the image into its `width`, `height`, and row-major buffer of pixels.
+    #[inline]
+    pub fn into_raw(self) -> (usize, usize, Vec<Pixel>) {
+        (self.width, self.height, self.pixels)
+    }
+
+    /// Decode the image encoded in the bytes provided by `input`.
+    pub fn decode<R>(input: R) -> Result<Self, Error>
+    where
+        R: Read,
+    {
+        let (width, height, pixels) = decode(input)?;
+        let mut image = Self {
+            width,
+            height,
+            pixels: Vec::with_capacity(initial_capacity(width * height)),
+        };
+        for p in pixels {
+            image.pixels.push(p?);
+        }
+        Ok(image)
+    }
+
+    /// Decode the image file.
+    #[cfg(feature = "std")]
+    #[inline]
+    pub fn open<P>(path: P) -> Result<Self, Error>
+    where
+        P: AsRef<Path>,
+    {
+        Self::decode(File::open(path)?)
+    }
+
+    /// Save the image to a file, choosing the format from the path's extension. Files
+    /// ending in `.qoi` are encoded by this crate. With the `image` feature enabled,
+    /// any other format the `image` crate supports (like `.png` or `.bmp`) can be saved
+    /// as well. Fails with [`Error::UnsupportedExtension`] for any other extension.
+    #[cfg(feature = "std")]
+    pub fn save<P>(&self, path: P) -> Result<(), Error>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        let ext = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+
+        if ext == "qoi" {
+            let width = NonZeroUsize::new(self.width).ok_or(Error::NoImageSize)?;
+            let height = NonZeroUsize::new(self.height).ok_or(Error::NoImageSize)?;
+            let mut output = BufWriter::new(File::create(path)?);
+            crate::encode(width, height, self.pixels.iter().copied(), &mut output)?;
+            output.flush()?;
+            return Ok(());
+        }
+
+        #[cfg(feature = "image")]
+        if ::image::ImageFormat::from_extension(&ext).is_some() {
+            let bytes: Vec<u8> = self
+                .pixels
+                .iter()
+                .flat_map(|&p| <[u8; 4]>::from(p))
+                .collect();
+            return ::image::save_buffer(
+                path,
+                &bytes,
+                self.width as u32,
+                self.height as u32,
+                ::image::ExtendedColorType::Rgba8,
+            )
+            .map_err(|err| Error::Io(std::io::Error::other(err)));
+        }
+
+        Err(Error::UnsupportedExtension(ext))
+    }
+
+    /// The width of the image.
+    #[inline]
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /// The height of the image.
+    #[inline]
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// The image's pixels, in row-major order.
+    #[inline]
+    pub fn pixels(&self) -> &[Pixel] {
+        &self.pixels
+    }
+
+    /// The image's pixels, in row-major order.
+    #[inline]
+    pub fn pixels_mut(&mut self) -> &mut [Pixel] {
+        &mut self.pixels
+    }
+
+    /// Check if both images are the same size, and every pixel is within `tol` of the
+    /// other image's (see [`Pixel::approx_eq`]).
+    pub fn approx_eq(&self, other: &Image, tol: u8) -> bool {
+        self.width == other.width
+            && self.height == other.height
+            && self
+                .pixels
+                .iter()
+                .zip(&other.pixels)
+                .all(|(a, b)| a.approx_eq(*b, tol))
+    }
+
+    /// Build a mask of the image's pixels, in row-major order, where each pixel is
+    /// `true` if its alpha is at least `cutoff` (see [`Pixel::threshold_alpha`]).
+    pub fn to_mask(&self, cutoff: u8) -> Vec<bool> {
+        self.pixels.iter().map(|p| p.a >= cutoff).collect()
+    }
+
+    /// Create a copy of the image resized to `width` by `height`, sampled with `filter`.
+    /// The `alpha` mode only matters for filters that blend pixels together.
+    pub fn resize(&self, width: usize, height: usize, filter: Filter, alpha: AlphaMode) -> Image {
+        if self.width == 0 || self.height == 0 {
+            return Image::new(width, height, Pixel::transparent());
+        }
+
+        let mut pixels = Vec::with_capacity(width * height);
+        match filter {
+            Filter::Nearest => {
+                for y in 0..height {
+                    let row = &self.pixels[((y * self.height) / height) * self.width..];
+                    pixels.extend((0..width).map(|x| row[(x * self.width) / width]));
+                }
+            }
+            Filter::Bilinear => {
+                // Convert the pixels to floats, premultiplying them if requested
+                let premultiply = alpha == AlphaMode::Premultiplied;
+                let src: Vec<[f32; 4]> = self
+                    .pixels
+                    .iter()
+                    .map(|p| {
+                        let a = p.a as f32;
+                        let m = if premultiply { a / 255.0 } else { 1.0 };
+                        [p.r as f32 * m, p.g as f32 * m, p.b as f32 * m, a]
+                    })
+                    .collect();
+
+                // Find the two source positions to blend between for a destination
+                // position, and how much of the second one to use
+                let sample = |i: usize, dst_len: usize, src_len: usize| {
+                    let pos = ((i as f32 + 0.5) * src_len as f32 / dst_len as f32 - 0.5).max(0.0);
+                    let i0 = (pos as usize).min(src_len - 1);
+                    let i1 = (i0 + 1).min(src_len - 1);
+                    (i0, i1, pos - i0 as f32)
+                };
+
+                for y in 0..height {
+                    let (y0, y1, ty) = sample(y, height, self.height);
+                    for x in 0..width {
+                        let (x0, x1, tx) = sample(x, width, self.width);
+                        let get = |x: usize, y: usize| src[y * self.width + x];
+                        let (p00, p10) = (get(x0, y0), get(x1, y0));
+                        let (p01, p11) = (get(x0, y1), get(x1, y1));
+                        let c: [f32; 4] = core::array::from_fn(|i| {
+                            let top = p00[i] + (p10[i] - p00[i]) * tx;
+                            let bot = p01[i] + (p11[i] - p01[i]) * tx;
+                            top + (bot - top) * ty
+                        });
+
+                        // Divide the alpha back out of the color channels
+                        let m = if premultiply && c[3] > 0.0 {
+                            255.0 / c[3]
+                        } else {
+                            1.0
+                        };
+                        let ch = |v: f32| (v + 0.5).clamp(0.0, 255.0) as u8;
+                        pixels.push(Pixel::rgba(
+                            ch(c[0] * m),
+                            ch(c[1] * m),
+                            ch(c[2] * m),
+                            ch(c[3]),
+                        ));
+                    }
+                }
+            }
+        }
+
+        Image {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    /// Render a preview of the image for display in a terminal that supports 24-bit color.
+    ///
+    /// The image is downsampled (nearest neighbor) to fit within `max_width` columns, and
+    /// each character cell draws two vertically stacked pixels using a half-block glyph,
+    /// so the preview keeps the image's aspect ratio. Alpha is ignored.
+    pub fn to_ansi_string(&self, max_width: usize) -> String {
+        let mut out = String::new();
+        if self.width == 0 || self.height == 0 || max_width == 0 {
+            return out;
+        }
+
+        // Figure out the size of the preview, in pixels
+        let cols = self.width.min(max_width);
+        let rows = ((self.height * cols) / self.width).max(1);
+
+        // Nearest-neighbor sample the image at a preview position
+        let sample = |x: usize, y: usize| {
+            self.pixels[((y * self.height) / rows) * self.width + (x * self.width) / cols]
+        };
+
+        for y in (0..rows).step_by(2) {
+            for x in 0..cols {
+                // The top pixel is drawn as the foreground of an upper half-block
+                let top = sample(x, y);
+                let _ = write!(out, "\x1b[38;2;{};{};{}m", top.r, top.g, top.b);
+
+                // The bottom pixel is drawn as the background, if there is one
+                if y + 1 < rows {
+                    let bot = sample(x, y + 1);
+                    let _ = write!(out, "\x1b[48;2;{};{};{}m", bot.r, bot.g, bot.b);
+                } else {
+                    out.push_str("\x1b[49m");
+                }
+                out.push('\u{2580}');
+            }
+            out.push_str("\x1b[0m\n");
+        }
+
+        out
+    }
+}
+
+impl Debug for Image {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Image")
+            .field("width", &self.width)
+            .field("height", &self.height)
+            .field("pixels", &format_args!("[<{} pixels>]", self.pixels.len()))
+            .finish()
+    }
+}
+
+/// Shrinks an image by a whole-number factor as its pixels are supplied in row-major
+/// order, averaging each block of pixels into one. Only one row of blocks is kept in
+/// memory at a time, along with the output.
+pub(crate) struct Downsampler {
+    width: usize,
+    height: usize,
+    factor: usize,
+    out_width: usize,
+    out_height: usize,
+    sums: Vec<[u64; 4]>,
+    x: usize,
+    y: usize,
+    output: Vec<Pixel>,
+}
+
+impl Downsampler {
+    /// Create a downsampler that shrinks a `width` by `height` image so that neither
+    /// of its sides exceed `max_dim`. The size usually comes from a header, so room is
+    /// only made up front for as much as [`initial_capacity`] allows, and the rest is
+    /// allocated as the pixels arrive.
+    pub fn new(width: usize, height: usize, max_dim: usize) -> Self {
+        // Find the downsample factor and the resulting image size
+        let factor = width.max(height).div_ceil(max_dim.max(1)).max(1);
+        let out_width = width.div_ceil(factor);
+        let out_height = height.div_ceil(factor);
+        Self {
+            width,
+            height,
+            factor,
+            out_width,
+            out_height,
+            sums: Vec::with_capacity(initial_capacity(out_width)),
+            x: 0,
+            y: 0,
+            output: Vec::with_capacity(initial_capacity(out_width.saturating_mul(out_height))),
+        }
+    }
+
+    /// Supply the next pixel of the image.
+    pub fn push(&mut self, p: Pixel) {
+        // Add the pixel to the running total for its block, which the first row of
+        // pixels reaches each of in turn
+        let block = self.x / self.factor;
+        if block == self.sums.len() {
+            self.sums.push([0; 4]);
+        }
+        let sum = &mut self.sums[block];
+        sum[0] += p.r as u64;
+        sum[1] += p.g as u64;
+        sum[2] += p.b as u64;
+        sum[3] += p.a as u64;
+
+        self.x += 1;
+        if self.x == self.width {
+            self.x = 0;
+            self.y += 1;
+
+            // If we've finished a row of blocks, average them into output pixels
+            if self.y.is_multiple_of(self.factor) || self.y == self.height {
+                let rows = (self.y - 1) % self.factor + 1;
+                for (i, sum) in self.sums.iter_mut().enumerate() {
+                    let count = (rows * self.factor.min(self.width - i * self.factor)) as u64;
+                    let avg = |c: u64| ((c + count / 2) / count) as u8;
+                    self.output.push(Pixel::rgba(
+                        avg(sum[0]),
+                        avg(sum[1]),
+                        avg(sum[2]),
+                        avg(sum[3]),
+                    ));
+                    *sum = [0; 4];
+                }
+            }
+        }
+    }
+
+    /// Get the shrunken image's `width`, `height`, and pixels.
+    pub fn finish(self) -> (usize, usize, Vec<Pixel>) {
+        (self.out_width, self.out_height, self.output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consts::MAX_PREALLOC;
+
+    #[test]
+    fn ansi_previews_draw_two_pixels_per_cell() {
+        let red = Pixel::rgb(255, 0, 0);
+        let cell = "\x1b[38;2;255;0;0m\x1b[48;2;255;0;0m\u{2580}";
+        let image = Image::new(4, 4, red);
+        assert_eq!(image.to_ansi_string(2), [cell, cell, "\x1b[0m\n"].concat());
+        let row = [cell; 4].concat() + "\x1b[0m\n";
+        assert_eq!(image.to_ansi_string(8), row.repeat(2));
+
+        // An odd row at the bottom leaves the background alone
+        let image = Image::new(1, 1, red);
+        assert_eq!(
+            image.to_ansi_string(1),
+            "\x1b[38;2;255;0;0m\x1b[49m\u{2580}\x1b[0m\n"
+        );
+        assert_eq!(image.to_ansi_string(0), "");
+    }
+
+    #[test]
+    fn downsamplers_average_blocks() {
+        // A 3x2 image shrunk by a factor of 2, where the last column is a block on its own
+        let mut thumb = Downsampler::new(3, 2, 2);
+        for p in [0, 10, 100, 20, 30, 200] {
+            thumb.push(Pixel::rgba(p, 0, 0, 255));
+        }
+        let (w, h, pixels) = thumb.finish();
+        assert_eq!((w, h), (2, 1));
+        assert_eq!(
+            pixels,
+            [Pixel::rgba(15, 0, 0, 255), Pixel::rgba(150, 0, 0, 255)]
+        );
+    }
+
+    #[test]
+    fn downsamplers_dont_trust_the_size() {
+        let max = u32::MAX as usize;
+        let thumb = Downsampler::new(max, max, usize::MAX);
+        assert!(thumb.sums.capacity() <= MAX_PREALLOC);
+        assert!(thumb.output.capacity() <= MAX_PREALLOC);
+    }
+
+    #[test]
+    fn compares_images_within_a_tolerance() {
+        let image = Image::new(3, 2, Pixel::rgba(10, 20, 30, 40));
+        let mut near = image.clone();
+        near.pixels_mut()[4].b += 1;
+        assert!(image.approx_eq(&image, 0));
+        assert!(!image.approx_eq(&near, 0));
+        assert!(image.approx_eq(&near, 1));
+
+        near.pixels_mut()[4].b += 1;
+        assert!(!image.approx_eq(&near, 1));
+        assert!(!image.approx_eq(&Image::new(2, 3, Pixel::rgba(10, 20, 30, 40)), 255));
+    }
+
+    #[test]
+    fn moves_raw_pixels_in_and_out() {
+        let pixels = vec![Pixel::gray(7); 6];
+        let image = Image::from_raw(3, 2, pixels.clone()).unwrap();
+        assert_eq!(image.into_raw(), (3, 2, pixels.clone()));
+        assert!(matches!(
+            Image::from_raw(4, 2, pixels),
+            Err(Error::LengthMismatch {
+                expected: 8,
+                got: 6
+            })
+        ));
+        assert!(matches!(
+            Image::from_raw(usize::MAX / 2 + 1, 2, Vec::new()),
+            Err(Error::ImageTooLarge { height: 2, .. })
+        ));
+    }
+
+    #[test]
+    fn premultiplied_resizes_keep_edges_bright() {
+        let red = Pixel::rgb(255, 0, 0);
+        let image = Image::from_raw(2, 1, vec![red, Pixel::transparent()]).unwrap();
+
+        let resized = image.resize(3, 1, Filter::Bilinear, AlphaMode::Premultiplied);
+        assert_eq!(resized.pixels()[0], red);
+        assert_eq!(resized.pixels()[1], Pixel::rgba(255, 0, 0, 128));
+
+        // Blending straight alpha lets the transparent black darken the red
+        let resized = image.resize(3, 1, Filter::Bilinear, AlphaMode::Straight);
+        assert_eq!(resized.pixels()[1], Pixel::rgba(128, 0, 0, 128));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn saves_and_reopens_qoi_files() {
+        let dir = std::env::temp_dir();
+        let path = dir.join(format!("qoi-test-{}-save.QOI", std::process::id()));
+        let pixels = (0..12)
+            .map(|i| Pixel::rgba(i * 20, 5, 9, 255 - i))
+            .collect();
+        let image = Image::from_raw(4, 3, pixels).unwrap();
+        image.save(&path).unwrap();
+        let reopened = Image::open(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(reopened.unwrap(), image);
+
+        let path = dir.join("qoi-test-save.unknown");
+        assert!(matches!(
+            image.save(&path),
+            Err(Error::UnsupportedExtension(ext)) if ext == "unknown"
+        ));
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn summarizes_images_in_debug_output() {
+        let image = Image::new(300, 200, Pixel::gray(1));
+        assert_eq!(
+            alloc::format!("{:?}", image),
+            "Image { width: 300, height: 200, pixels: [<60000 pixels>] }"
+        );
+
+        let mut other = image.clone();
+        assert_eq!(image, other);
+        other.pixels_mut()[59999] = Pixel::gray(2);
+        assert_ne!(image, other);
+        assert_ne!(image, Image::new(200, 300, Pixel::gray(1)));
+    }
+}
@@ -0,0 +1,229 @@
+use crate::{
+    decode, encode_with_options, initial_capacity, Channels, EncodeOptions, Error, Header, Pixel,
+    Pixels,
+};
+use ::image::error::{
+    DecodingError, EncodingError, ParameterError, ParameterErrorKind, UnsupportedError,
+    UnsupportedErrorKind,
+};
+use ::image::{
+    ColorType, ExtendedColorType, ImageDecoder, ImageEncoder, ImageError, ImageFormat, ImageResult,
+    RgbaImage,
+};
+use std::io::{Read, Write};
+use std::num::NonZeroUsize;
+
+// Adapters for plugging QOI images into the `image` crate's decoding and encoding
+// pipelines, like `DynamicImage::from_decoder` and `DynamicImage::write_with_encoder`.
+
+/// A decoder for the `image` crate, which decodes an image with this crate's decoder.
+/// Images with 3 channels are decoded as [`ColorType::Rgb8`], and images with 4 as
+/// [`ColorType::Rgba8`].
+pub struct QoiDecoder<R> {
+    header: Header,
+    pixels: Pixels<R>,
+}
+
+impl<R> QoiDecoder<R>
+where
+    R: Read,
+{
+    /// Create a decoder for the image in `input`, reading its header right away.
+    pub fn new(input: R) -> Result<Self, Error> {
+        let (_, _, pixels) = decode(input)?;
+        Ok(Self {
+            header: pixels.header(),
+            pixels,
+        })
+    }
+}
+
+impl<R> ImageDecoder for QoiDecoder<R>
+where
+    R: Read,
+{
+    #[inline]
+    fn dimensions(&self) -> (u32, u32) {
+        (self.header.width as u32, self.header.height as u32)
+    }
+
+    #[inline]
+    fn color_type(&self) -> ColorType {
+        if self.header.channels == Channels::Rgb {
+            ColorType::Rgb8
+        } else {
+            ColorType::Rgba8
+        }
+    }
+
+    fn read_image(self, buf: &mut [u8]) -> ImageResult<()> {
+        assert_eq!(buf.len() as u64, self.total_bytes());
+        let bpp = self.color_type().bytes_per_pixel() as usize;
+        for (bytes, p) in buf.chunks_exact_mut(bpp).zip(self.pixels) {
+            let p = p.map_err(decoding_error)?;
+            bytes.copy_from_slice(&<[u8; 4]>::from(p)[..bpp]);
+        }
+        Ok(())
+    }
+
+    #[inline]
+    fn read_image_boxed(self: Box<Self>, buf: &mut [u8]) -> ImageResult<()> {
+        (*self).read_image(buf)
+    }
+}
+
+/// An encoder for the `image` crate, which encodes an image with this crate's encoder.
+/// Only [`ExtendedColorType::Rgb8`] and [`ExtendedColorType::Rgba8`] images can be
+/// encoded, and are marked as having 3 and 4 channels respectively.
+pub struct QoiEncoder<W> {
+    output: W,
+}
+
+impl<W> QoiEncoder<W>
+where
+    W: Write,
+{
+    /// Create an encoder that writes the image to `output`.
+    #[inline]
+    pub fn new(output: W) -> Self {
+        Self { output }
+    }
+}
+
+impl<W> ImageEncoder for QoiEncoder<W>
+where
+    W: Write,
+{
+    fn write_image(
+        self,
+        buf: &[u8],
+        width: u32,
+        height: u32,
+        color_type: ExtendedColorType,
+    ) -> ImageResult<()> {
+        let channels = match color_type {
+            ExtendedColorType::Rgb8 => Channels::Rgb,
+            ExtendedColorType::Rgba8 => Channels::Rgba,
+            _ => {
+                return Err(ImageError::Unsupported(
+                    UnsupportedError::from_format_and_kind(
+                        ImageFormat::Qoi.into(),
+                        UnsupportedErrorKind::Color(color_type),
+                    ),
+                ))
+            }
+        };
+        assert_eq!(
+            buf.len() as u64,
+            width as u64 * height as u64 * u8::from(channels) as u64
+        );
+
+        let dimension_error = || {
+            ImageError::Parameter(ParameterError::from_kind(
+                ParameterErrorKind::DimensionMismatch,
+            ))
+        };
+        let width = NonZeroUsize::new(width as usize).ok_or_else(dimension_error)?;
+        let height = NonZeroUsize::new(height as usize).ok_or_else(dimension_error)?;
+
+        let pixels = buf.chunks_exact(channels as usize).map(|c| match *c {
+            [r, g, b] => Pixel::rgb(r, g, b),
+            _ => Pixel::rgba(c[0], c[1], c[2], c[3]),
+        });
+        let options = EncodeOptions {
+            channels,
+            ..Default::default()
+        };
+        encode_with_options(width, height, pixels, self.output, options).map_err(
+            |err| match err {
+                Error::Io(err) => ImageError::IoError(err),
+                err => ImageError::Encoding(EncodingError::new(ImageFormat::Qoi.into(), err)),
+            },
+        )?;
+        Ok(())
+    }
+}
+
+impl<R> Pixels<R>
+where
+    R: Read,
+{
+    /// Decode the rest of the pixels into an [`RgbaImage`], returning the first error the
+    /// parser encounters. Fails with [`Error::DimensionMismatch`] before decoding any
+    /// pixels if the image's size isn't `width` by `height`, and with
+    /// [`Error::Truncated`] if some of its pixels have already been decoded.
+    pub fn into_rgba_image(self, width: usize, height: usize) -> Result<RgbaImage, Error> {
+        let header = self.header();
+        if (header.width, header.height) != (width, height) {
+            return Err(Error::DimensionMismatch {
+                expected: (width, height),
+                found: (header.width, header.height),
+            });
+        }
+
+        let mut buf = Vec::with_capacity(initial_capacity(width * height) * 4);
+        for p in self {
+            buf.extend_from_slice(&<[u8; 4]>::from(p?));
+        }
+        let got = buf.len() / 4;
+        RgbaImage::from_raw(width as u32, height as u32, buf).ok_or(Error::Truncated {
+            expected: width * height,
+            got,
+        })
+    }
+}
+
+fn decoding_error(err: Error) -> ImageError {
+    match err {
+        Error::Io(err) => ImageError::IoError(err),
+        err => ImageError::Decoding(DecodingError::new(ImageFormat::Qoi.into(), err)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{SPEC_IMAGE, SPEC_PIXELS};
+
+    #[test]
+    fn collects_pixels_into_an_rgba_image() {
+        let (_, _, pixels) = decode(&SPEC_IMAGE[..]).unwrap();
+        let image = pixels.into_rgba_image(4, 2).unwrap();
+        assert_eq!(image.dimensions(), (4, 2));
+        for (p, expected) in image.pixels().zip(SPEC_PIXELS) {
+            assert_eq!(p.0, <[u8; 4]>::from(expected));
+        }
+    }
+
+    #[test]
+    fn rejects_the_wrong_dimensions() {
+        let (_, _, pixels) = decode(&SPEC_IMAGE[..]).unwrap();
+        assert!(matches!(
+            pixels.into_rgba_image(2, 4),
+            Err(Error::DimensionMismatch {
+                expected: (2, 4),
+                found: (4, 2),
+            })
+        ));
+    }
+
+    #[test]
+    fn rejects_partly_decoded_pixels() {
+        let (_, _, mut pixels) = decode(&SPEC_IMAGE[..]).unwrap();
+        pixels.next().unwrap().unwrap();
+        assert!(matches!(
+            pixels.into_rgba_image(4, 2),
+            Err(Error::Truncated {
+                expected: 8,
+                got: 7
+            })
+        ));
+    }
+
+    #[test]
+    fn propagates_decode_errors() {
+        let truncated = &SPEC_IMAGE[..SPEC_IMAGE.len() - 12];
+        let (_, _, pixels) = decode(truncated).unwrap();
+        assert!(pixels.into_rgba_image(4, 2).is_err());
+    }
+}
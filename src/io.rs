@@ -0,0 +1,193 @@
+//! The I/O traits that images are read from and written to.
+//!
+//! With the `std` feature (on by default), these are just the ones from [`std::io`], so
+//! any reader or writer from the standard library can be used. Without it, this module
+//! provides minimal versions of them instead, implemented for byte slices and vectors,
+//! which can be implemented for other sources of data as well.
+
+#[cfg(feature = "std")]
+pub use std::io::{sink, Error, ErrorKind, Read, Result, Sink, Write};
+
+#[cfg(not(feature = "std"))]
+pub use self::no_std::*;
+
+#[cfg(not(feature = "std"))]
+mod no_std {
+    use alloc::vec::Vec;
+    use core::fmt::{Debug, Display, Formatter};
+
+    /// A specialized result type for I/O operations.
+    pub type Result<T> = core::result::Result<T, Error>;
+
+    /// The category of an I/O [`Error`].
+    #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+    #[non_exhaustive]
+    pub enum ErrorKind {
+        /// The data ended before everything that was needed could be read.
+        UnexpectedEof,
+
+        /// A write returned without writing any bytes.
+        WriteZero,
+
+        /// The operation was interrupted, and can be retried.
+        Interrupted,
+
+        /// A parameter was incorrect.
+        InvalidInput,
+
+        /// Any other error.
+        Other,
+    }
+
+    /// An I/O error, which only carries its [`ErrorKind`].
+    #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+    pub struct Error {
+        kind: ErrorKind,
+    }
+
+    impl Error {
+        /// Create an error of the given kind.
+        #[inline]
+        pub const fn new(kind: ErrorKind) -> Self {
+            Self { kind }
+        }
+
+        /// Get the kind of error this is.
+        #[inline]
+        pub const fn kind(&self) -> ErrorKind {
+            self.kind
+        }
+    }
+
+    impl From<ErrorKind> for Error {
+        #[inline]
+        fn from(kind: ErrorKind) -> Self {
+            Self::new(kind)
+        }
+    }
+
+    impl Display for Error {
+        #[inline]
+        fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+            Debug::fmt(&self.kind, f)
+        }
+    }
+
+    impl core::error::Error for Error {}
+
+    /// A source of bytes.
+    pub trait Read {
+        /// Read some bytes into `buf`, returning how many were read. Returning 0 means
+        /// the source has run out of bytes, unless `buf` is empty.
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize>;
+
+        /// Read exactly enough bytes to fill `buf`, failing with
+        /// [`ErrorKind::UnexpectedEof`] if the source runs out first.
+        fn read_exact(&mut self, mut buf: &mut [u8]) -> Result<()> {
+            while !buf.is_empty() {
+                match self.read(buf) {
+                    Ok(0) => return Err(ErrorKind::UnexpectedEof.into()),
+                    Ok(n) => buf = &mut buf[n..],
+                    Err(err) if err.kind() == ErrorKind::Interrupted => {}
+                    Err(err) => return Err(err),
+                }
+            }
+            Ok(())
+        }
+
+        /// Read all of the bytes left in the source onto the end of `buf`, returning
+        /// how many were read.
+        fn read_to_end(&mut self, buf: &mut Vec<u8>) -> Result<usize> {
+            let start = buf.len();
+            let mut chunk = [0; 256];
+            loop {
+                match self.read(&mut chunk) {
+                    Ok(0) => return Ok(buf.len() - start),
+                    Ok(n) => buf.extend_from_slice(&chunk[..n]),
+                    Err(err) if err.kind() == ErrorKind::Interrupted => {}
+                    Err(err) => return Err(err),
+                }
+            }
+        }
+    }
+
+    /// A destination for bytes.
+    pub trait Write {
+        /// Write some of the bytes in `buf`, returning how many were written.
+        fn write(&mut self, buf: &[u8]) -> Result<usize>;
+
+        /// Write all of the bytes in `buf`, failing with [`ErrorKind::WriteZero`] if
+        /// the destination stops accepting them.
+        fn write_all(&mut self, mut buf: &[u8]) -> Result<()> {
+            while !buf.is_empty() {
+                match self.write(buf) {
+                    Ok(0) => return Err(ErrorKind::WriteZero.into()),
+                    Ok(n) => buf = &buf[n..],
+                    Err(err) if err.kind() == ErrorKind::Interrupted => {}
+                    Err(err) => return Err(err),
+                }
+            }
+            Ok(())
+        }
+
+        /// Flush any buffered bytes to the destination.
+        fn flush(&mut self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    impl<R: Read + ?Sized> Read for &mut R {
+        #[inline]
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+            (**self).read(buf)
+        }
+    }
+
+    impl Read for &[u8] {
+        #[inline]
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+            let n = buf.len().min(self.len());
+            let (a, b) = self.split_at(n);
+            buf[..n].copy_from_slice(a);
+            *self = b;
+            Ok(n)
+        }
+    }
+
+    impl<W: Write + ?Sized> Write for &mut W {
+        #[inline]
+        fn write(&mut self, buf: &[u8]) -> Result<usize> {
+            (**self).write(buf)
+        }
+
+        #[inline]
+        fn flush(&mut self) -> Result<()> {
+            (**self).flush()
+        }
+    }
+
+    impl Write for Vec<u8> {
+        #[inline]
+        fn write(&mut self, buf: &[u8]) -> Result<usize> {
+            self.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+    }
+
+    /// A writer that discards everything written to it.
+    #[derive(Copy, Clone, Debug, Default)]
+    pub struct Sink;
+
+    /// Create a writer that discards everything written to it.
+    #[inline]
+    pub const fn sink() -> Sink {
+        Sink
+    }
+
+    impl Write for Sink {
+        #[inline]
+        fn write(&mut self, buf: &[u8]) -> Result<usize> {
+            Ok(buf.len())
+        }
+    }
+}
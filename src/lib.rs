@@ -1,12 +1,41 @@
 #![doc = include_str!("../README.md")]
+#![cfg_attr(not(feature = "std"), no_std)]
 
+#[cfg(not(feature = "alloc"))]
+compile_error!("either the `std` or `alloc` feature must be enabled");
+
+extern crate alloc;
+
+#[cfg(feature = "tokio")]
+mod async_io;
 pub(crate) mod consts;
 mod decode;
 mod encode;
 mod error;
+#[cfg(feature = "std")]
+mod ext;
+#[cfg(test)]
+mod fixtures;
+mod image;
+#[cfg(feature = "image")]
+mod interop;
+pub mod io;
+mod palette;
 mod pixel;
+#[cfg(feature = "std")]
+mod player;
 
+#[cfg(feature = "tokio")]
+pub use async_io::*;
 pub use decode::*;
 pub use encode::*;
 pub use error::*;
+#[cfg(feature = "std")]
+pub use ext::*;
+pub use image::*;
+#[cfg(feature = "image")]
+pub use interop::*;
+pub use palette::*;
 pub use pixel::*;
+#[cfg(feature = "std")]
+pub use player::*;
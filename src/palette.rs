@@ -0,0 +1,130 @@
+use crate::Pixel;
+use alloc::vec::Vec;
+
+/// An index over a palette of colors, for quickly finding the palette color
+/// nearest to a pixel. Distance is measured as the squared euclidean distance
+/// between the pixels' RGBA components.
+///
+/// The colors are stored in a 4D k-d tree, so searches only have to visit a
+/// small part of the palette, which matters when mapping a large image to a
+/// large palette.
+#[derive(Clone, Debug)]
+pub struct PaletteIndex {
+    // The palette colors and their original indices, arranged so that the middle
+    // of every range is the node that splits the rest of that range in two
+    nodes: Vec<([u8; 4], usize)>,
+}
+
+impl PaletteIndex {
+    /// Build an index over the colors of `palette`.
+    pub fn new(palette: &[Pixel]) -> Self {
+        let mut nodes: Vec<([u8; 4], usize)> = palette
+            .iter()
+            .enumerate()
+            .map(|(i, &p)| (p.into(), i))
+            .collect();
+        build(&mut nodes, 0);
+        Self { nodes }
+    }
+
+    /// The amount of colors in the palette.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Check if the palette has no colors in it.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// Find the index, in the original palette, of the color nearest to `px`. If
+    /// several colors are equally near, the one that comes first in the palette
+    /// is returned.
+    ///
+    /// Panics if the palette is empty.
+    pub fn nearest(&self, px: Pixel) -> usize {
+        assert!(!self.is_empty(), "can't search an empty palette");
+        let mut best = (u32::MAX, usize::MAX);
+        search(&self.nodes, 0, px.into(), &mut best);
+        best.1
+    }
+}
+
+/// Arrange `nodes` into a k-d tree, splitting on the axis for the depth.
+fn build(nodes: &mut [([u8; 4], usize)], depth: usize) {
+    if nodes.len() <= 1 {
+        return;
+    }
+    let axis = depth % 4;
+    let mid = nodes.len() / 2;
+    nodes.select_nth_unstable_by_key(mid, |(c, _)| c[axis]);
+    let (left, right) = nodes.split_at_mut(mid);
+    build(left, depth + 1);
+    build(&mut right[1..], depth + 1);
+}
+
+/// Search the k-d tree for the nearest color, keeping the best `(distance, index)`
+/// found so far. Comparing the index as well picks the earliest of equal colors.
+fn search(nodes: &[([u8; 4], usize)], depth: usize, target: [u8; 4], best: &mut (u32, usize)) {
+    if nodes.is_empty() {
+        return;
+    }
+    let mid = nodes.len() / 2;
+    let (color, index) = nodes[mid];
+    let dist = color
+        .iter()
+        .zip(&target)
+        .map(|(&a, &b)| (a.abs_diff(b) as u32).pow(2))
+        .sum();
+    *best = (*best).min((dist, index));
+
+    // Search the side the target is on first, and only search the other side
+    // if it could hold something nearer than what we've found
+    let axis = depth % 4;
+    let (near, far) = if target[axis] < color[axis] {
+        (&nodes[..mid], &nodes[mid + 1..])
+    } else {
+        (&nodes[mid + 1..], &nodes[..mid])
+    };
+    search(near, depth + 1, target, best);
+    if (target[axis].abs_diff(color[axis]) as u32).pow(2) <= best.0 {
+        search(far, depth + 1, target, best);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_the_same_colors_as_a_brute_force_search() {
+        let mut seed = 0x9e37_79b9_u32;
+        let mut random = move || {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            Pixel::from(seed.to_le_bytes())
+        };
+
+        // Include a repeated color, so ties have to pick the first of them
+        let mut palette: Vec<Pixel> = (0..15).map(|_| random()).collect();
+        palette.push(palette[3]);
+        palette.push(Pixel::transparent());
+        let index = PaletteIndex::new(&palette);
+        assert_eq!(index.len(), palette.len());
+
+        let dist = |a: Pixel, b: Pixel| -> u32 {
+            let (a, b): ([u8; 4], [u8; 4]) = (a.into(), b.into());
+            a.iter()
+                .zip(&b)
+                .map(|(&a, &b)| (a.abs_diff(b) as u32).pow(2))
+                .sum()
+        };
+        for px in (0..500).map(|_| random()).chain(palette.iter().copied()) {
+            let brute = (0..palette.len()).min_by_key(|&i| (dist(palette[i], px), i));
+            assert_eq!(index.nearest(px), brute.unwrap(), "{}", px);
+        }
+    }
+}
@@ -1,6 +1,13 @@
-// An RGBA pixel.
+use crate::Error;
+use alloc::format;
+use alloc::string::{String, ToString};
+use core::fmt::{Display, Formatter};
+
+// An RGBA pixel. Pixels are ordered by their red, green, blue, and alpha channels, in
+// that order.
 #[repr(C)]
-#[derive(Default, Copy, Clone, Eq, PartialEq, Debug)]
+#[derive(Default, Copy, Clone, Eq, PartialEq, Hash, PartialOrd, Ord, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Pixel {
     pub r: u8,
     pub g: u8,
@@ -27,14 +34,116 @@ impl Pixel {
         Self::rgba(r, g, b, 255)
     }
 
-    /// Hash the pixel's RGBA components together. This is used
-    /// by the encoder/decoder to create storage indices for the
-    /// running lookup table.
+    /// Create a gray pixel, with every color channel set to `v` and a full (255)
+    /// alpha channel.
+    #[inline]
+    pub const fn gray(v: u8) -> Self {
+        Self::rgba(v, v, v, 255)
+    }
+
+    /// Create a gray pixel, with every color channel set to `v` and the provided alpha.
     #[inline]
-    pub(crate) const fn hash(self) -> u8 {
+    pub const fn gray_alpha(v: u8, a: u8) -> Self {
+        Self::rgba(v, v, v, a)
+    }
+
+    /// The position (0 to 63) of the pixel in the running lookup table the encoder and
+    /// decoder keep, from hashing its RGBA components together as the QOI specification
+    /// does. An index chunk for this pixel would refer to this position.
+    #[inline]
+    pub const fn index(self) -> u8 {
+        let hash = self.r as u32 * 3 + self.g as u32 * 5 + self.b as u32 * 7 + self.a as u32 * 11;
+        (hash % 64) as u8
+    }
+
+    /// The hash used by the pre-release draft format.
+    #[inline]
+    pub(crate) const fn legacy_hash(self) -> u8 {
         self.r ^ self.g ^ self.b ^ self.a
     }
 
+    /// Check if every channel of this pixel is within `tol` of the other pixel's.
+    #[inline]
+    pub const fn approx_eq(self, other: Pixel, tol: u8) -> bool {
+        self.r.abs_diff(other.r) <= tol
+            && self.g.abs_diff(other.g) <= tol
+            && self.b.abs_diff(other.b) <= tol
+            && self.a.abs_diff(other.a) <= tol
+    }
+
+    /// Make the pixel fully opaque if its alpha is at least `cutoff`, or fully
+    /// transparent otherwise. The color channels are left untouched.
+    #[inline]
+    pub const fn threshold_alpha(self, cutoff: u8) -> Pixel {
+        let a = if self.a >= cutoff { 255 } else { 0 };
+        Self::rgba(self.r, self.g, self.b, a)
+    }
+
+    /// Convert the pixel from straight alpha (which QOI images store) to premultiplied
+    /// alpha, by scaling its color channels by its alpha. A fully transparent pixel
+    /// becomes `(0, 0, 0, 0)`, and a fully opaque one is left unchanged.
+    #[inline]
+    pub const fn premultiply(self) -> Pixel {
+        const fn scale(c: u8, a: u8) -> u8 {
+            ((c as u16 * a as u16 + 127) / 255) as u8
+        }
+        let a = self.a;
+        Self::rgba(scale(self.r, a), scale(self.g, a), scale(self.b, a), a)
+    }
+
+    /// Convert the pixel from premultiplied alpha back to straight alpha, by dividing
+    /// its color channels by its alpha, clamping them to 255. A fully transparent pixel
+    /// is returned unchanged, since its colors can't be recovered.
+    #[inline]
+    pub const fn unpremultiply(self) -> Pixel {
+        if self.a == 0 {
+            return self;
+        }
+        const fn scale(c: u8, a: u8) -> u8 {
+            let c = (c as u16 * 255 + a as u16 / 2) / a as u16;
+            if c > 255 {
+                255
+            } else {
+                c as u8
+            }
+        }
+        let a = self.a;
+        Self::rgba(scale(self.r, a), scale(self.g, a), scale(self.b, a), a)
+    }
+
+    /// Blend the pixel over a `background` pixel (the Porter-Duff "source over"
+    /// operation), where both have straight alpha. A fully opaque pixel is returned
+    /// unchanged, and a fully transparent one just returns the background.
+    #[inline]
+    pub const fn over(self, background: Pixel) -> Pixel {
+        match self.a {
+            255 => return self,
+            0 => return background,
+            _ => {}
+        }
+
+        // The alpha of the result, and its color channels weighted by it, all scaled up
+        // by 255 so the math can be done with integers and rounded at the end
+        let (sa, da) = (self.a as u32, background.a as u32);
+        let a = sa * 255 + da * (255 - sa);
+        const fn blend(sc: u8, dc: u8, sa: u32, da: u32, a: u32) -> u8 {
+            let c = sc as u32 * sa * 255 + dc as u32 * da * (255 - sa);
+            ((c + a / 2) / a) as u8
+        }
+        Self::rgba(
+            blend(self.r, background.r, sa, da, a),
+            blend(self.g, background.g, sa, da, a),
+            blend(self.b, background.b, sa, da, a),
+            ((a + 127) / 255) as u8,
+        )
+    }
+
+    /// Get the pixel's RGB components as an array, discarding alpha.
+    #[inline]
+    pub const fn to_rgb_array(self) -> [u8; 3] {
+        [self.r, self.g, self.b]
+    }
+
     /// Pack the pixel into a 32-bit RGBA integer.
     #[inline]
     pub fn pack(self) -> u32 {
@@ -51,6 +160,188 @@ impl Pixel {
             a: packed as u8,
         }
     }
+
+    /// Pack the pixel into a 16-bit RGB565 integer, truncating each channel's
+    /// low bits and discarding alpha.
+    #[inline]
+    pub fn to_rgb565(self) -> u16 {
+        ((self.r as u16) >> 3) << 11 | ((self.g as u16) >> 2) << 5 | ((self.b as u16) >> 3)
+    }
+
+    /// Unpack an opaque pixel from a 16-bit RGB565 integer. Each channel's bits
+    /// are replicated into the low bits, so the full 0-255 range is covered.
+    #[inline]
+    pub fn from_rgb565(packed: u16) -> Self {
+        let r = ((packed >> 11) & 0x1f) as u8;
+        let g = ((packed >> 5) & 0x3f) as u8;
+        let b = (packed & 0x1f) as u8;
+        Self::rgb(r << 3 | r >> 2, g << 2 | g >> 4, b << 3 | b >> 2)
+    }
+
+    /// Pack the pixel into a 16-bit RGBA4444 integer, truncating each channel's
+    /// low bits.
+    #[inline]
+    pub fn to_rgba4444(self) -> u16 {
+        ((self.r as u16) >> 4) << 12
+            | ((self.g as u16) >> 4) << 8
+            | ((self.b as u16) >> 4) << 4
+            | ((self.a as u16) >> 4)
+    }
+
+    /// Unpack the pixel from a 16-bit RGBA4444 integer. Each channel's bits are
+    /// replicated into the low bits, so the full 0-255 range is covered.
+    #[inline]
+    pub fn from_rgba4444(packed: u16) -> Self {
+        let expand = |v: u16| ((v & 0xf) as u8) * 0x11;
+        Self::rgba(
+            expand(packed >> 12),
+            expand(packed >> 8),
+            expand(packed >> 4),
+            expand(packed),
+        )
+    }
+
+    /// Convert the pixel to full-range BT.601 (JPEG-style) `(y, cb, cr)` components,
+    /// discarding alpha.
+    pub fn to_ycbcr(self) -> (u8, u8, u8) {
+        let (r, g, b) = (self.r as f32, self.g as f32, self.b as f32);
+        let y = 0.299 * r + 0.587 * g + 0.114 * b;
+        let cb = 128.0 - 0.168736 * r - 0.331264 * g + 0.5 * b;
+        let cr = 128.0 + 0.5 * r - 0.418688 * g - 0.081312 * b;
+        let ch = |v: f32| (v + 0.5).clamp(0.0, 255.0) as u8;
+        (ch(y), ch(cb), ch(cr))
+    }
+
+    /// Create a pixel from full-range BT.601 (JPEG-style) `y`, `cb`, and `cr`
+    /// components, with the provided alpha.
+    pub fn from_ycbcr(y: u8, cb: u8, cr: u8, a: u8) -> Self {
+        let (y, cb, cr) = (y as f32, cb as f32 - 128.0, cr as f32 - 128.0);
+        let r = y + 1.402 * cr;
+        let g = y - 0.344136 * cb - 0.714136 * cr;
+        let b = y + 1.772 * cb;
+        let ch = |v: f32| (v + 0.5).clamp(0.0, 255.0) as u8;
+        Self::rgba(ch(r), ch(g), ch(b), a)
+    }
+
+    /// Format the pixel as a CSS color, either `rgb(r, g, b)` if the pixel is
+    /// opaque, or `rgba(r, g, b, a)` with the alpha as a number from 0 to 1.
+    pub fn to_css(self) -> String {
+        if self.a == 255 {
+            format!("rgb({}, {}, {})", self.r, self.g, self.b)
+        } else {
+            let a = format!("{:.3}", self.a as f32 / 255.0);
+            let a = a.trim_end_matches('0').trim_end_matches('.');
+            format!("rgba({}, {}, {}, {})", self.r, self.g, self.b, a)
+        }
+    }
+
+    /// Parse a CSS color in the `rgb(r, g, b)`, `rgba(r, g, b, a)`, or `#hex` forms.
+    /// Color channels can be numbers from 0 to 255 or percentages, and alpha can be a
+    /// number from 0 to 1 or a percentage. Returns `None` if the color is invalid,
+    /// including if `rgb` isn't given exactly 3 arguments, or `rgba` exactly 4.
+    pub fn from_css(s: &str) -> Option<Self> {
+        let s = s.trim();
+        if let Some(hex) = s.strip_prefix('#') {
+            return Self::parse_hex(hex);
+        }
+
+        // Get the comma-separated arguments of the function, and how many it takes
+        let (args, count) = match s.strip_prefix("rgba(") {
+            Some(args) => (args, 4),
+            None => (s.strip_prefix("rgb(")?, 3),
+        };
+        let args = args.strip_suffix(')')?;
+        if args.split(',').count() != count {
+            return None;
+        }
+        let mut args = args.split(',').map(str::trim);
+
+        // Parse a number, which can be a percentage of `max`, into a clamped channel value
+        let parse = |arg: Option<&str>, max: f32| -> Option<u8> {
+            let arg = arg?;
+            let v = match arg.strip_suffix('%') {
+                Some(pct) => pct.trim_end().parse::<f32>().ok()? * max / 100.0,
+                None => arg.parse::<f32>().ok()?,
+            };
+            (!v.is_nan()).then(|| ((v / max).clamp(0.0, 1.0) * 255.0 + 0.5) as u8)
+        };
+
+        let r = parse(args.next(), 255.0)?;
+        let g = parse(args.next(), 255.0)?;
+        let b = parse(args.next(), 255.0)?;
+        let a = match args.next() {
+            Some(arg) => parse(Some(arg), 1.0)?,
+            None => 255,
+        };
+        Some(Self::rgba(r, g, b, a))
+    }
+
+    /// Parse a hex color in the `#RGB`, `#RGBA`, `#RRGGBB`, or `#RRGGBBAA` forms, with or
+    /// without the leading `#`. This is the inverse of the pixel's [`Display`] format.
+    /// Fails with [`Error::InvalidHex`] if the string isn't a valid hex color.
+    pub fn from_hex(s: &str) -> Result<Self, Error> {
+        let hex = s.strip_prefix('#').unwrap_or(s);
+        Self::parse_hex(hex).ok_or_else(|| Error::InvalidHex(s.to_string()))
+    }
+
+    /// Parse the hex digits of a color in the `RGB`, `RGBA`, `RRGGBB`, or `RRGGBBAA` forms.
+    fn parse_hex(hex: &str) -> Option<Self> {
+        let digits = hex.as_bytes();
+        let digit = |i: usize| (digits[i] as char).to_digit(16).map(|d| d as u8);
+        match digits.len() {
+            3 | 4 => {
+                let c = |i: usize| digit(i).map(|d| d * 0x11);
+                let a = if digits.len() == 4 { c(3)? } else { 255 };
+                Some(Self::rgba(c(0)?, c(1)?, c(2)?, a))
+            }
+            6 | 8 => {
+                let c = |i: usize| Some(digit(i * 2)? << 4 | digit(i * 2 + 1)?);
+                let a = if digits.len() == 8 { c(3)? } else { 255 };
+                Some(Self::rgba(c(0)?, c(1)?, c(2)?, a))
+            }
+            _ => None,
+        }
+    }
+
+    /// Convert the pixel to a vector of RGBA components normalized to the `0.0..=1.0` range.
+    #[cfg(feature = "glam")]
+    #[inline]
+    pub fn to_vec4(self) -> glam::Vec4 {
+        glam::Vec4::new(self.r as f32, self.g as f32, self.b as f32, self.a as f32) / 255.0
+    }
+
+    /// Convert a vector of normalized RGBA components to a pixel. Components
+    /// outside of the `0.0..=1.0` range are clamped.
+    #[cfg(feature = "glam")]
+    #[inline]
+    pub fn from_vec4(v: glam::Vec4) -> Self {
+        let c = v.clamp(glam::Vec4::ZERO, glam::Vec4::ONE) * 255.0 + 0.5;
+        Self::rgba(c.x as u8, c.y as u8, c.z as u8, c.w as u8)
+    }
+}
+
+/// Count the occurrences of each value of each channel in `pixels`, returning
+/// a histogram for each of the R, G, B, and A channels, in that order.
+pub fn histogram(pixels: &[Pixel]) -> [[u32; 256]; 4] {
+    let mut hist = [[0; 256]; 4];
+    for p in pixels {
+        hist[0][p.r as usize] += 1;
+        hist[1][p.g as usize] += 1;
+        hist[2][p.b as usize] += 1;
+        hist[3][p.a as usize] += 1;
+    }
+    hist
+}
+
+/// Formats the pixel as an 8-digit hex color, like `#ff0000ff`.
+impl Display for Pixel {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "#{:02x}{:02x}{:02x}{:02x}",
+            self.r, self.g, self.b, self.a
+        )
+    }
 }
 
 impl From<u32> for Pixel {
@@ -60,10 +351,10 @@ impl From<u32> for Pixel {
     }
 }
 
-impl Into<u32> for Pixel {
+impl From<Pixel> for u32 {
     #[inline]
-    fn into(self) -> u32 {
-        self.pack()
+    fn from(px: Pixel) -> Self {
+        px.pack()
     }
 }
 
@@ -74,10 +365,17 @@ impl From<(u8, u8, u8, u8)> for Pixel {
     }
 }
 
-impl Into<(u8, u8, u8, u8)> for Pixel {
+impl From<Pixel> for (u8, u8, u8, u8) {
+    #[inline]
+    fn from(px: Pixel) -> Self {
+        (px.r, px.g, px.b, px.a)
+    }
+}
+
+impl From<(u8, u8, u8)> for Pixel {
     #[inline]
-    fn into(self) -> (u8, u8, u8, u8) {
-        (self.r, self.g, self.b, self.a)
+    fn from((r, g, b): (u8, u8, u8)) -> Self {
+        Self::rgb(r, g, b)
     }
 }
 
@@ -88,9 +386,357 @@ impl From<[u8; 4]> for Pixel {
     }
 }
 
-impl Into<[u8; 4]> for Pixel {
+impl From<Pixel> for [u8; 4] {
+    #[inline]
+    fn from(px: Pixel) -> Self {
+        [px.r, px.g, px.b, px.a]
+    }
+}
+
+impl From<[u8; 3]> for Pixel {
+    #[inline]
+    fn from([r, g, b]: [u8; 3]) -> Self {
+        Self::rgb(r, g, b)
+    }
+}
+
+/// Fails with [`Error::LengthMismatch`] unless the slice holds exactly 4 bytes.
+impl TryFrom<&[u8]> for Pixel {
+    type Error = Error;
+
+    #[inline]
+    fn try_from(bytes: &[u8]) -> Result<Self, Error> {
+        match *bytes {
+            [r, g, b, a] => Ok(Self { r, g, b, a }),
+            _ => Err(Error::LengthMismatch {
+                expected: 4,
+                got: bytes.len(),
+            }),
+        }
+    }
+}
+
+#[cfg(feature = "rgb")]
+impl From<rgb::RGBA8> for Pixel {
+    #[inline]
+    fn from(px: rgb::RGBA8) -> Self {
+        Self::rgba(px.r, px.g, px.b, px.a)
+    }
+}
+
+#[cfg(feature = "rgb")]
+impl From<Pixel> for rgb::RGBA8 {
+    #[inline]
+    fn from(px: Pixel) -> Self {
+        rgb::RGBA8::new(px.r, px.g, px.b, px.a)
+    }
+}
+
+#[cfg(feature = "rgb")]
+impl From<rgb::RGB8> for Pixel {
+    #[inline]
+    fn from(px: rgb::RGB8) -> Self {
+        Self::rgb(px.r, px.g, px.b)
+    }
+}
+
+/// Converting to `RGB8` discards the pixel's alpha channel.
+#[cfg(feature = "rgb")]
+impl From<Pixel> for rgb::RGB8 {
     #[inline]
-    fn into(self) -> [u8; 4] {
-        [self.r, self.g, self.b, self.a]
+    fn from(px: Pixel) -> Self {
+        rgb::RGB8::new(px.r, px.g, px.b)
+    }
+}
+
+// SAFETY: `Pixel` is `repr(C)` and made of four `u8`s, so it has no padding, and
+// every bit pattern (including all zeroes) is a valid pixel.
+#[cfg(feature = "bytemuck")]
+unsafe impl bytemuck::Zeroable for Pixel {}
+
+#[cfg(feature = "bytemuck")]
+unsafe impl bytemuck::Pod for Pixel {}
+
+/// View a slice of RGBA bytes as a slice of pixels, without copying it. Returns
+/// [`Error::BufferTooSmall`] if the slice's length isn't a multiple of 4, with its length
+/// rounded up to the next multiple of 4 as `needed`. Trimming the slice down to the
+/// multiple below works just as well, if the bytes past it aren't part of a pixel.
+#[cfg(feature = "bytemuck")]
+pub fn pixels_from_bytes(bytes: &[u8]) -> Result<&[Pixel], Error> {
+    if !bytes.len().is_multiple_of(4) {
+        return Err(Error::BufferTooSmall {
+            needed: bytes.len().next_multiple_of(4),
+            got: bytes.len(),
+        });
+    }
+    Ok(bytemuck::cast_slice(bytes))
+}
+
+/// Serializes a [`Pixel`] as a single `u32` (see [`Pixel::pack`]), instead of as a struct
+/// with `r`, `g`, `b`, and `a` fields, for a more compact form. Use it on a field with
+/// `#[serde(with = "qoi::packed_pixel")]`.
+#[cfg(feature = "serde")]
+pub mod packed_pixel {
+    use super::Pixel;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    /// Serialize the pixel as a packed `u32`.
+    #[inline]
+    pub fn serialize<S>(px: &Pixel, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        px.pack().serialize(serializer)
+    }
+
+    /// Deserialize a pixel from a packed `u32`.
+    #[inline]
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Pixel, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        u32::deserialize(deserializer).map(Pixel::unpack)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn index_is_the_spec_hash() {
+        assert_eq!(Pixel::rgba(0, 0, 0, 0).index(), 0);
+        assert_eq!(Pixel::rgba(0, 0, 0, 255).index(), 53);
+        assert_eq!(Pixel::rgba(10, 20, 30, 255).index(), 9);
+        assert_eq!(Pixel::rgba(255, 255, 255, 255).index(), 38);
+        for v in 0..=255 {
+            let px = Pixel::rgba(v, v.wrapping_mul(7), !v, v / 3);
+            let (r, g, b, a) = (px.r as u32, px.g as u32, px.b as u32, px.a as u32);
+            assert_eq!(px.index() as u32, (r * 3 + g * 5 + b * 7 + a * 11) % 64);
+        }
+    }
+
+    #[cfg(feature = "rgb")]
+    #[test]
+    fn converts_rgb_crate_pixels() {
+        let px = rgb::RGBA8::new(10, 20, 30, 40);
+        assert_eq!(Pixel::from(px), Pixel::rgba(10, 20, 30, 40));
+        assert_eq!(rgb::RGBA8::from(Pixel::from(px)), px);
+        assert_eq!(rgb::RGB8::from(Pixel::from(px)), rgb::RGB8::new(10, 20, 30));
+        assert_eq!(
+            Pixel::from(rgb::RGB8::new(10, 20, 30)),
+            Pixel::rgb(10, 20, 30)
+        );
+    }
+
+    #[test]
+    fn packs_into_16_bits() {
+        for v in 0..=255 {
+            let px = Pixel::rgba(v, !v, v.wrapping_mul(3), v);
+            let rgb = Pixel::from_rgb565(px.to_rgb565());
+            assert!(rgb.r.abs_diff(px.r) <= 7 && rgb.b.abs_diff(px.b) <= 7);
+            assert!(rgb.g.abs_diff(px.g) <= 3 && rgb.a == 255);
+            assert!(Pixel::from_rgba4444(px.to_rgba4444()).approx_eq(px, 15));
+        }
+
+        // The extremes of each channel survive exactly
+        for px in [
+            Pixel::rgb(0, 0, 0),
+            Pixel::rgb(255, 255, 255),
+            Pixel::rgb(255, 0, 255),
+        ] {
+            assert_eq!(Pixel::from_rgb565(px.to_rgb565()), px);
+            assert_eq!(Pixel::from_rgba4444(px.to_rgba4444()), px);
+        }
+        assert_eq!(Pixel::rgb(255, 0, 0).to_rgb565(), 0xf800);
+        assert_eq!(Pixel::rgba(255, 0, 0, 0x80).to_rgba4444(), 0xf008);
+    }
+
+    #[cfg(feature = "glam")]
+    #[test]
+    fn converts_glam_vectors() {
+        let px = Pixel::rgba(255, 0, 51, 102);
+        assert_eq!(px.to_vec4(), glam::Vec4::new(1.0, 0.0, 0.2, 0.4));
+        assert_eq!(Pixel::from_vec4(px.to_vec4()), px);
+        assert_eq!(
+            Pixel::from_vec4(glam::Vec4::new(2.0, -1.0, 0.5, 1.0)),
+            Pixel::rgba(255, 0, 128, 255)
+        );
+    }
+
+    #[test]
+    fn parses_css_colors() {
+        assert_eq!(
+            Pixel::from_css("rgba(255, 0, 0, 0.5)"),
+            Some(Pixel::rgba(255, 0, 0, 128))
+        );
+        assert_eq!(
+            Pixel::from_css(" rgb(100%, 50%, 0) "),
+            Some(Pixel::rgb(255, 128, 0))
+        );
+        assert_eq!(
+            Pixel::from_css("rgba(1,2,3,40%)"),
+            Some(Pixel::rgba(1, 2, 3, 102))
+        );
+        assert_eq!(Pixel::from_css("#f80"), Some(Pixel::rgb(255, 136, 0)));
+        assert_eq!(
+            Pixel::from_css("#ff000080"),
+            Some(Pixel::rgba(255, 0, 0, 128))
+        );
+
+        // Each function only takes its own number of arguments
+        assert_eq!(Pixel::from_css("rgb(255, 0, 0, 0.5)"), None);
+        assert_eq!(Pixel::from_css("rgba(255, 0, 0)"), None);
+        assert_eq!(Pixel::from_css("rgba(255, 0, 0, 1, 1)"), None);
+        assert_eq!(Pixel::from_css("rgb(255, 0)"), None);
+        assert_eq!(Pixel::from_css("rgb(255, 0, nan)"), None);
+        assert_eq!(Pixel::from_css("#ff00"), Some(Pixel::rgba(255, 255, 0, 0)));
+        assert_eq!(Pixel::from_css("#ff0g"), None);
+
+        for px in [
+            Pixel::rgb(1, 2, 3),
+            Pixel::rgba(255, 0, 0, 128),
+            Pixel::rgba(0, 0, 0, 0),
+        ] {
+            assert_eq!(Pixel::from_css(&px.to_css()), Some(px));
+        }
+    }
+
+    #[test]
+    fn drops_alpha_into_rgb_arrays() {
+        assert_eq!(Pixel::rgba(1, 2, 3, 4).to_rgb_array(), [1, 2, 3]);
+        assert_eq!(Pixel::transparent().to_rgb_array(), [0, 0, 0]);
+    }
+
+    #[test]
+    fn histograms_count_each_channel() {
+        let hist = histogram(&[Pixel::rgba(1, 2, 3, 4); 10]);
+        for (channel, value) in hist.iter().zip([1, 2, 3, 4]) {
+            assert_eq!(channel[value], 10);
+            assert_eq!(channel.iter().filter(|&&n| n > 0).count(), 1);
+        }
+        assert_eq!(histogram(&[]), [[0; 256]; 4]);
+    }
+
+    #[test]
+    fn thresholds_alpha() {
+        let px = Pixel::rgba(1, 2, 3, 128);
+        assert_eq!(px.threshold_alpha(100), Pixel::rgba(1, 2, 3, 255));
+        assert_eq!(px.threshold_alpha(200), Pixel::rgba(1, 2, 3, 0));
+        assert_eq!(px.threshold_alpha(128), Pixel::rgba(1, 2, 3, 255));
+    }
+
+    #[test]
+    fn converts_to_ycbcr() {
+        for v in [0, 1, 127, 128, 254, 255] {
+            assert_eq!(Pixel::gray(v).to_ycbcr(), (v, 128, 128));
+            assert_eq!(Pixel::from_ycbcr(v, 128, 128, 9), Pixel::gray_alpha(v, 9));
+        }
+        let red = Pixel::rgb(255, 0, 0);
+        assert_eq!(red.to_ycbcr(), (76, 85, 255));
+        let (y, cb, cr) = red.to_ycbcr();
+        assert!(Pixel::from_ycbcr(y, cb, cr, 255).approx_eq(red, 1));
+    }
+
+    #[test]
+    fn converts_slices_of_exactly_4_bytes() {
+        let bytes = [1, 2, 3, 4, 5];
+        assert_eq!(
+            Pixel::try_from(&bytes[..4]).unwrap(),
+            Pixel::rgba(1, 2, 3, 4)
+        );
+        for len in [3, 5] {
+            assert!(matches!(
+                Pixel::try_from(&bytes[..len]),
+                Err(Error::LengthMismatch { expected: 4, got }) if got == len
+            ));
+        }
+    }
+
+    #[cfg(feature = "bytemuck")]
+    #[test]
+    fn casts_pixels_to_bytes_and_back() {
+        let pixels = alloc::vec![Pixel::rgba(1, 2, 3, 4), Pixel::rgba(5, 6, 7, 8)];
+        let bytes: &[u8] = bytemuck::cast_slice(&pixels);
+        assert_eq!(bytes, [1, 2, 3, 4, 5, 6, 7, 8]);
+        assert_eq!(bytemuck::cast_slice::<u8, Pixel>(bytes), pixels);
+        assert_eq!(pixels_from_bytes(bytes).unwrap(), pixels);
+        assert!(matches!(
+            pixels_from_bytes(&bytes[1..]),
+            Err(Error::BufferTooSmall { needed: 8, got: 7 })
+        ));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serializes_pixels() {
+        #[derive(serde::Serialize, serde::Deserialize, PartialEq, Debug)]
+        struct Packed(#[serde(with = "packed_pixel")] Pixel);
+
+        let px = Pixel::rgba(255, 0, 16, 128);
+        let json = serde_json::to_string(&px).unwrap();
+        assert_eq!(json, r#"{"r":255,"g":0,"b":16,"a":128}"#);
+        assert_eq!(serde_json::from_str::<Pixel>(&json).unwrap(), px);
+        let bytes = bincode::serialize(&px).unwrap();
+        assert_eq!(bytes, [255, 0, 16, 128]);
+        assert_eq!(bincode::deserialize::<Pixel>(&bytes).unwrap(), px);
+
+        // Packed pixels are a single number
+        let json = serde_json::to_string(&Packed(px)).unwrap();
+        assert_eq!(json, 0xff00_1080_u32.to_string());
+        assert_eq!(serde_json::from_str::<Packed>(&json).unwrap(), Packed(px));
+        let bytes = bincode::serialize(&Packed(px)).unwrap();
+        assert_eq!(bincode::deserialize::<Packed>(&bytes).unwrap(), Packed(px));
+    }
+
+    #[test]
+    fn premultiplies_alpha() {
+        for px in [Pixel::rgb(1, 2, 3), Pixel::rgb(255, 128, 0)] {
+            assert_eq!(px.premultiply(), px);
+            assert_eq!(px.premultiply().premultiply(), px);
+            assert_eq!(px.unpremultiply(), px);
+        }
+        assert_eq!(
+            Pixel::rgba(255, 100, 0, 128).premultiply(),
+            Pixel::rgba(128, 50, 0, 128)
+        );
+        assert_eq!(
+            Pixel::rgba(128, 50, 0, 128).unpremultiply(),
+            Pixel::rgba(255, 100, 0, 128)
+        );
+        assert_eq!(Pixel::rgba(9, 9, 9, 0).premultiply(), Pixel::transparent());
+        assert_eq!(
+            Pixel::rgba(9, 9, 9, 0).unpremultiply(),
+            Pixel::rgba(9, 9, 9, 0)
+        );
+    }
+
+    #[test]
+    fn composites_over_backgrounds() {
+        let background = Pixel::rgba(0, 0, 255, 255);
+        assert_eq!(Pixel::rgba(255, 0, 0, 0).over(background), background);
+        assert_eq!(
+            Pixel::rgb(255, 0, 0).over(background),
+            Pixel::rgb(255, 0, 0)
+        );
+        assert_eq!(
+            Pixel::rgba(255, 0, 0, 128).over(background),
+            Pixel::rgb(128, 0, 127)
+        );
+
+        // Over a transparent background, the pixel keeps its own color
+        let px = Pixel::rgba(255, 0, 0, 128);
+        assert_eq!(px.over(Pixel::transparent()), px);
+        let half = Pixel::rgba(0, 0, 255, 128);
+        assert_eq!(px.over(half), Pixel::rgba(170, 0, 85, 192));
+    }
+
+    #[test]
+    fn displays_as_hex() {
+        assert_eq!(Pixel::rgb(255, 0, 0).to_string(), "#ff0000ff");
+        assert_eq!(Pixel::rgba(1, 0xab, 16, 0).to_string(), "#01ab1000");
+        let px = Pixel::rgba(18, 52, 86, 120);
+        assert_eq!(Pixel::from_hex(&px.to_string()).unwrap(), px);
+        assert!(matches!(Pixel::from_hex("#12345"), Err(Error::InvalidHex(s)) if s == "#12345"));
     }
 }
@@ -0,0 +1,115 @@
+use crate::{initial_capacity, Decoder, Error, Pixel};
+use std::io::{BufRead, BufReader, Read};
+
+/// Plays back a stream of concatenated images as video frames, decoding each frame
+/// into the next slot of a ring of pixel buffers. The buffers are cleared and reused,
+/// so once each slot has held a frame, playback doesn't need to allocate.
+pub struct FramePlayer<R> {
+    input: BufReader<R>,
+    decoder: Decoder,
+    slot: usize,
+}
+
+/// Where a frame was decoded to by [`FramePlayer::next_frame`], and its size.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct Frame {
+    /// The index of the buffer in the ring that holds the frame's pixels.
+    pub slot: usize,
+
+    /// The width of the frame.
+    pub width: usize,
+
+    /// The height of the frame.
+    pub height: usize,
+}
+
+impl<R> FramePlayer<R>
+where
+    R: Read,
+{
+    /// Create a player that reads frames from `input`, starting at the first slot.
+    pub fn new(input: R) -> Self {
+        Self {
+            input: BufReader::new(input),
+            // Each frame has to be read exactly, so the next one starts where it ends
+            decoder: Decoder::new().read_buffer(1),
+            slot: 0,
+        }
+    }
+
+    /// The index of the slot the next frame will be decoded into.
+    #[inline]
+    pub fn next_slot(&self) -> usize {
+        self.slot
+    }
+
+    /// Decode the next frame into the next slot of `ring`, which then moves on to the
+    /// slot after it, wrapping around at the end of the ring. Returns `None` once the
+    /// stream ends cleanly between frames.
+    ///
+    /// Panics if `ring` is empty.
+    pub fn next_frame(&mut self, ring: &mut [Vec<Pixel>]) -> Result<Option<Frame>, Error> {
+        assert!(!ring.is_empty(), "the ring must have at least one buffer");
+        if self.input.fill_buf()?.is_empty() {
+            return Ok(None);
+        }
+
+        let slot = self.slot % ring.len();
+        let (width, height, pixels) = self.decoder.decode(&mut self.input)?;
+        let buf = &mut ring[slot];
+        buf.clear();
+        buf.reserve(initial_capacity(width * height));
+        for p in pixels {
+            buf.push(p?);
+        }
+
+        self.slot = (slot + 1) % ring.len();
+        Ok(Some(Frame {
+            slot,
+            width,
+            height,
+        }))
+    }
+
+    /// Unwrap the player, returning the buffered reader the frames were read from.
+    #[inline]
+    pub fn into_inner(self) -> BufReader<R> {
+        self.input
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::num::NonZeroUsize;
+
+    #[test]
+    fn cycles_frames_through_the_ring() {
+        let frames: Vec<Vec<Pixel>> = (0..3).map(|f| vec![Pixel::gray(f * 50); 6]).collect();
+        let mut stream = Vec::new();
+        for frame in &frames {
+            let (width, height) = (NonZeroUsize::new(3).unwrap(), NonZeroUsize::new(2).unwrap());
+            crate::encode(width, height, frame.iter().copied(), &mut stream).unwrap();
+        }
+
+        let mut player = FramePlayer::new(&stream[..]);
+        let mut ring = vec![Vec::new(), Vec::new()];
+        for (i, frame) in frames.iter().enumerate() {
+            assert_eq!(player.next_slot(), i % 2);
+            let played = player.next_frame(&mut ring).unwrap().unwrap();
+            assert_eq!(
+                played,
+                Frame {
+                    slot: i % 2,
+                    width: 3,
+                    height: 2
+                }
+            );
+            assert_eq!(&ring[i % 2], frame);
+        }
+
+        // The second buffer still holds the second frame
+        assert_eq!(ring[1], frames[1]);
+        assert_eq!(player.next_frame(&mut ring).unwrap(), None);
+    }
+}